@@ -0,0 +1,63 @@
+#!/usr/bin/env bash
+# Times `rst` on the generated tree behind the scan table in README.md:
+# 200,000 small files in 2,000 directories, with a .gitignore at the top and
+# in each of the 40 top-level directories.
+#
+#   scripts/bench-scan.sh [TREE_DIR] [RUNS]
+#
+# The tree is generated once and reused. Runs read from a warm page cache,
+# after one untimed warm-up run. The README figures were taken on one core;
+# prefix the command with `taskset -c 0` to compare with them.
+set -euo pipefail
+
+tree=${1:-${TMPDIR:-/tmp}/rosetree-bench-200k}
+runs=${2:-5}
+repo=$(cd "$(dirname "$0")/.." && pwd)
+
+cargo build --release --quiet --manifest-path "$repo/Cargo.toml"
+rst=$repo/target/release/rst
+
+if [ ! -e "$tree/.done" ]; then
+    echo "Generating $tree..." >&2
+    rm -rf "$tree"
+    mkdir -p "$tree"
+    printf 'target/\n*.log\n' > "$tree/.gitignore"
+    extensions=(rs py md txt json)
+    for a in $(seq 0 39); do
+        for b in $(seq 0 49); do
+            dir=$tree/m$a/p$b
+            mkdir -p "$dir"
+            for f in $(seq 0 99); do
+                printf 'line %d\nline %d\nline %d\n' "$f" "$f" "$f" \
+                    > "$dir/f$f.${extensions[f % 5]}"
+            done
+        done
+        printf '*.tmp\n' > "$tree/m$a/.gitignore"
+    done
+    touch "$tree/.done"
+fi
+
+report=$(mktemp)
+trap 'rm -f "$report"' EXIT
+
+# Runs `rst` in the tree with the given arguments and prints the best and
+# median wall time in seconds.
+bench() {
+    local label=$1
+    shift
+    (cd "$tree" && "$rst" "$@" -o "$report" < /dev/null > /dev/null 2>&1) || true
+    local times=()
+    for _ in $(seq "$runs"); do
+        local start=$EPOCHREALTIME
+        (cd "$tree" && "$rst" "$@" -o "$report" < /dev/null > /dev/null 2>&1) || true
+        times+=("$(awk -v start="$start" -v end="$EPOCHREALTIME" 'BEGIN { print end - start }')")
+    done
+    printf '%s\n' "${times[@]}" | sort -n | awk -v label="$label" '
+        { t[NR] = $1 }
+        END { printf "%-40s best %.2f s  median %.2f s\n", label, t[1], t[int((NR + 1) / 2)] }'
+}
+
+echo "rst $("$rst" --version | cut -d' ' -f2), $(nproc) core(s), $runs runs each" >&2
+bench "Scan with .gitignore rules" --gitignore --no-cache --ext none
+bench "Scan without .gitignore rules" --no-gitignore --no-cache --ext none
+bench "Whole run with --all" --gitignore --no-cache --all
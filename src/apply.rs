@@ -0,0 +1,719 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::unpack::{escapes_root, is_closing_fence, opening_fence_len, safe_relative_path};
+use rosetree::normalize_separators;
+
+/// Apply unified diffs and whole-file blocks from a model response.
+#[derive(Args)]
+pub struct ApplyArgs {
+    /// File holding the response, or `-` for stdin (the default)
+    #[arg(value_name = "RESPONSE")]
+    response: Option<PathBuf>,
+
+    /// Working tree the changes apply to
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    into: PathBuf,
+
+    /// Check that every change applies without writing any file
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Context lines a hunk may drop at each end to find a match
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    fuzz: usize,
+}
+
+/// One change found in a response.
+enum Change {
+    /// A fenced block holding the complete new content of a file.
+    Replace {
+        path: String,
+        content: String,
+    },
+    Patch(FilePatch),
+}
+
+/// The hunks of a unified diff for one file.
+struct FilePatch {
+    path: String,
+    /// `--- /dev/null`: the patch creates the file.
+    creates: bool,
+    /// `+++ /dev/null`: the patch deletes the file.
+    deletes: bool,
+    hunks: Vec<Hunk>,
+}
+
+struct Hunk {
+    old_start: usize,
+    lines: Vec<HunkLine>,
+}
+
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+/// Content of a file before and after the changes that target it.
+struct FileState {
+    path: String,
+    target: PathBuf,
+    original: Option<String>,
+    current: Option<String>,
+    conflicts: Vec<String>,
+}
+
+/// Read the response, apply every change in memory, then write the files
+/// that applied cleanly.
+pub fn run(args: &ApplyArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let text = match &args.response {
+        Some(path) if path.as_os_str() != "-" => fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?,
+        _ => {
+            if io::stdin().is_terminal() {
+                return Err("Pass a response file or pipe the response into stdin".into());
+            }
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+    let changes = parse_response(&text)?;
+    if changes.is_empty() {
+        return Err("No unified diffs or file blocks found in the response".into());
+    }
+    if !args.into.is_dir() {
+        return Err(format!("Not a directory: {}", args.into.display()).into());
+    }
+    let root = args.into.canonicalize()?;
+
+    let mut files: Vec<FileState> = Vec::new();
+    let mut index_by_path: HashMap<String, usize> = HashMap::new();
+    let mut rejected = 0;
+    for change in changes {
+        let path = match &change {
+            Change::Replace { path, .. } => path,
+            Change::Patch(patch) => &patch.path,
+        };
+        let state = if let Some(&i) = index_by_path.get(path) {
+            &mut files[i]
+        } else {
+            let Some(relative) = safe_relative_path(path) else {
+                eprintln!("  reject    {path} (unsafe path)");
+                rejected += 1;
+                continue;
+            };
+            let target = args.into.join(relative);
+            if escapes_root(&root, &target)? {
+                eprintln!("  reject    {path} (outside {})", args.into.display());
+                rejected += 1;
+                continue;
+            }
+            let original = match fs::read_to_string(&target) {
+                Ok(content) => Some(content),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(format!("Failed to read {}: {e}", target.display()).into()),
+            };
+            index_by_path.insert(path.clone(), files.len());
+            files.push(FileState {
+                path: path.clone(),
+                target,
+                current: original.clone(),
+                original,
+                conflicts: Vec::new(),
+            });
+            files.last_mut().expect("just pushed")
+        };
+        apply_change(state, change, args.fuzz);
+    }
+
+    let mut written = 0;
+    let mut conflicts = 0;
+    for state in &files {
+        if !state.conflicts.is_empty() {
+            for conflict in &state.conflicts {
+                eprintln!("  conflict  {}: {conflict}", state.path);
+            }
+            conflicts += state.conflicts.len();
+            continue;
+        }
+        let action = match (&state.original, &state.current) {
+            (original, current) if original == current => "unchanged",
+            (None, Some(_)) => "create",
+            (Some(_), Some(_)) => "modify",
+            (_, None) => "delete",
+        };
+        eprintln!("  {action:<9} {}", state.path);
+        if action == "unchanged" {
+            continue;
+        }
+        if !args.dry_run {
+            write_state(state)?;
+        }
+        written += 1;
+    }
+
+    let verb = if args.dry_run {
+        "would change"
+    } else {
+        "changed"
+    };
+    eprintln!("\n{written} files {verb} ({conflicts} conflicts, {rejected} rejected)");
+    if conflicts + rejected > 0 {
+        return Err(format!(
+            "{} changes could not be applied; the files involved were left unchanged",
+            conflicts + rejected
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn write_state(state: &FileState) -> Result<(), String> {
+    match &state.current {
+        Some(content) => {
+            if let Some(parent) = state.target.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+            }
+            fs::write(&state.target, content)
+                .map_err(|e| format!("Failed to write {}: {e}", state.target.display()))
+        }
+        None => fs::remove_file(&state.target)
+            .map_err(|e| format!("Failed to delete {}: {e}", state.target.display())),
+    }
+}
+
+fn apply_change(state: &mut FileState, change: Change, fuzz: usize) {
+    match change {
+        Change::Replace { content, .. } => state.current = Some(content),
+        Change::Patch(patch) => match (&state.current, patch.creates) {
+            (None, false) => state.conflicts.push("file does not exist".to_string()),
+            (Some(_), true) => state.conflicts.push("file already exists".to_string()),
+            (current, _) => {
+                let current = current.as_deref().unwrap_or_default();
+                match apply_hunks(current, &patch.hunks, fuzz) {
+                    Ok(_) if patch.deletes => state.current = None,
+                    Ok(patched) => state.current = Some(patched),
+                    Err(failed) => {
+                        let total = patch.hunks.len();
+                        state.conflicts.extend(
+                            failed
+                                .into_iter()
+                                .map(|n| format!("hunk {n} of {total} does not match")),
+                        );
+                    }
+                }
+            }
+        },
+    }
+}
+
+/// Apply hunks in order, returning the numbers of the hunks that could not
+/// be placed. Each hunk is first tried where its header says, adjusted by
+/// the shift of earlier hunks, then ever further away; lines match when they
+/// are equal up to trailing whitespace. With `fuzz`, up to that many context
+/// lines may be dropped from either end of a hunk.
+fn apply_hunks(content: &str, hunks: &[Hunk], fuzz: usize) -> Result<String, Vec<usize>> {
+    let eol = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let missing_final_newline = !content.is_empty() && !content.ends_with('\n');
+    let mut lines: Vec<String> = content.split_inclusive('\n').map(str::to_string).collect();
+    if let Some(last) = lines.last_mut().filter(|_| missing_final_newline) {
+        last.push_str(eol);
+    }
+
+    let mut failed = Vec::new();
+    let mut offset: isize = 0;
+    for (n, hunk) in hunks.iter().enumerate() {
+        let Some((pos, front, body)) = locate_hunk(&lines, hunk, offset, fuzz) else {
+            failed.push(n + 1);
+            continue;
+        };
+        let mut replacement = Vec::new();
+        let mut end = pos;
+        for line in body {
+            match line {
+                HunkLine::Context(_) => {
+                    replacement.push(lines[end].clone());
+                    end += 1;
+                }
+                HunkLine::Remove(_) => end += 1,
+                HunkLine::Add(text) => replacement.push(format!("{text}{eol}")),
+            }
+        }
+        let expected = hunk.old_start.saturating_sub(1) + front;
+        offset = pos.cast_signed() - expected.cast_signed() + replacement.len().cast_signed()
+            - (end - pos).cast_signed();
+        lines.splice(pos..end, replacement);
+    }
+    if !failed.is_empty() {
+        return Err(failed);
+    }
+
+    let mut patched = lines.concat();
+    if missing_final_newline && patched.ends_with(eol) {
+        patched.truncate(patched.len() - eol.len());
+    }
+    Ok(patched)
+}
+
+/// Find where a hunk applies, returning the start line, the number of
+/// leading context lines taken off as fuzz and the hunk lines that matched.
+fn locate_hunk<'a>(
+    lines: &[String],
+    hunk: &'a Hunk,
+    offset: isize,
+    fuzz: usize,
+) -> Option<(usize, usize, &'a [HunkLine])> {
+    let leading = hunk
+        .lines
+        .iter()
+        .take_while(|l| matches!(l, HunkLine::Context(_)))
+        .count();
+    let trailing = hunk
+        .lines
+        .iter()
+        .rev()
+        .take_while(|l| matches!(l, HunkLine::Context(_)))
+        .count();
+    let mut tried = Vec::new();
+    for f in 0..=fuzz {
+        let front = f.min(leading);
+        let back = f.min(trailing).min(hunk.lines.len() - front);
+        if tried.contains(&(front, back)) {
+            continue;
+        }
+        tried.push((front, back));
+
+        let body = &hunk.lines[front..hunk.lines.len() - back];
+        let old: Vec<&str> = body
+            .iter()
+            .filter_map(|l| match l {
+                HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect();
+        let expected = (hunk.old_start.saturating_sub(1) + front)
+            .saturating_add_signed(offset)
+            .min(lines.len());
+        // A pure insertion goes where the header says
+        if old.is_empty() {
+            return Some((expected, front, body));
+        }
+        if old.len() > lines.len() {
+            continue;
+        }
+        let last_start = lines.len() - old.len();
+        let matches_at = |start: usize| {
+            lines[start..start + old.len()]
+                .iter()
+                .zip(&old)
+                .all(|(line, old)| line.trim_end() == old.trim_end())
+        };
+        for distance in 0..=last_start.max(expected) {
+            let candidates = [
+                expected.checked_add(distance),
+                expected.checked_sub(distance),
+            ];
+            for start in candidates.into_iter().flatten() {
+                if start <= last_start && matches_at(start) {
+                    return Some((start, front, body));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Find unified diffs, bare or fenced, and fenced blocks under a heading
+/// naming a file, such as ``### `src/main.rs` ``. A file block whose fence
+/// is never closed makes the whole response malformed, since the response
+/// may have been cut off.
+fn parse_response(text: &str) -> Result<Vec<Change>, String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut changes = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some(path) = heading_path(lines[i]) {
+            let mut j = i + 1;
+            while j < lines.len() && lines[j].trim().is_empty() {
+                j += 1;
+            }
+            if let Some(open) = lines.get(j).and_then(|l| opening_fence_len(l.trim_end())) {
+                let language = lines[j][open..].trim();
+                let start = j + 1;
+                let mut end = start;
+                while end < lines.len() && !is_closing_fence(lines[end].trim_end(), open) {
+                    end += 1;
+                }
+                if end == lines.len() {
+                    return Err(format!("The code block of `{path}` is not closed"));
+                }
+                let block = &lines[start..end];
+                let is_diff = matches!(language, "diff" | "patch")
+                    || block
+                        .iter()
+                        .find(|l| !l.trim().is_empty())
+                        .is_some_and(|l| {
+                            l.starts_with("diff --git ")
+                                || l.starts_with("--- ")
+                                || l.starts_with("@@ ")
+                        });
+                if is_diff {
+                    changes.extend(parse_diff(block, Some(path)).into_iter().map(Change::Patch));
+                } else {
+                    let mut content = block.join("\n");
+                    content.push('\n');
+                    changes.push(Change::Replace {
+                        path: normalize_path(path),
+                        content,
+                    });
+                }
+                i = end + 1;
+                continue;
+            }
+        }
+        if is_file_header(&lines, i) {
+            let (patch, next) = parse_file_patch(&lines, i, None);
+            changes.extend(patch.map(Change::Patch));
+            i = next;
+            continue;
+        }
+        i += 1;
+    }
+    Ok(changes)
+}
+
+/// Parse every file patch in a fenced diff block. Hunks without file headers
+/// apply to `default_path`, taken from the heading above the block.
+fn parse_diff(lines: &[&str], default_path: Option<&str>) -> Vec<FilePatch> {
+    let mut patches = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if is_file_header(lines, i) || (lines[i].starts_with("@@ ") && default_path.is_some()) {
+            let (patch, next) = parse_file_patch(lines, i, default_path);
+            patches.extend(patch);
+            i = next;
+        } else {
+            i += 1;
+        }
+    }
+    patches
+}
+
+fn is_file_header(lines: &[&str], i: usize) -> bool {
+    lines[i].starts_with("--- ") && lines.get(i + 1).is_some_and(|l| l.starts_with("+++ "))
+}
+
+/// Parse the patch for one file starting at `i`, either at its `---`/`+++`
+/// header or, with `default_path`, directly at its first hunk.
+fn parse_file_patch(
+    lines: &[&str],
+    mut i: usize,
+    default_path: Option<&str>,
+) -> (Option<FilePatch>, usize) {
+    let (old_path, new_path) = if is_file_header(lines, i) {
+        let paths = diff_paths(&lines[i][4..], &lines[i + 1][4..]);
+        i += 2;
+        paths
+    } else {
+        (
+            default_path.map(normalize_path),
+            default_path.map(normalize_path),
+        )
+    };
+
+    let mut hunks = Vec::new();
+    while let Some((old_start, mut old_left, mut new_left)) =
+        lines.get(i).and_then(|l| parse_hunk_header(l))
+    {
+        i += 1;
+        let mut hunk_lines = Vec::new();
+        while let Some(line) = lines.get(i) {
+            // A complete hunk ends here, so prose after a bare diff is left out
+            if old_left == 0 && new_left == 0 {
+                if line.starts_with('\\') {
+                    i += 1;
+                }
+                break;
+            }
+            if is_file_header(lines, i) || line.starts_with("@@ ") {
+                break;
+            }
+            let hunk_line = match line.chars().next() {
+                Some(' ') => HunkLine::Context(line[1..].to_string()),
+                Some('-') => HunkLine::Remove(line[1..].to_string()),
+                Some('+') => HunkLine::Add(line[1..].to_string()),
+                Some('\\') => {
+                    i += 1;
+                    continue;
+                }
+                // Blank context lines often lose their leading space
+                None if old_left > 0 && new_left > 0 => HunkLine::Context(String::new()),
+                _ => break,
+            };
+            match hunk_line {
+                HunkLine::Context(_) => {
+                    old_left = old_left.saturating_sub(1);
+                    new_left = new_left.saturating_sub(1);
+                }
+                HunkLine::Remove(_) => old_left = old_left.saturating_sub(1),
+                HunkLine::Add(_) => new_left = new_left.saturating_sub(1),
+            }
+            hunk_lines.push(hunk_line);
+            i += 1;
+        }
+        hunks.push(Hunk {
+            old_start,
+            lines: hunk_lines,
+        });
+    }
+
+    let patch = new_path.clone().or(old_path.clone()).map(|path| FilePatch {
+        path,
+        creates: old_path.is_none(),
+        deletes: new_path.is_none(),
+        hunks,
+    });
+    (patch, i)
+}
+
+/// Paths from `---`/`+++` headers, without timestamps and `a/`/`b/` prefixes.
+/// `/dev/null` stands for a missing side.
+fn diff_paths(old: &str, new: &str) -> (Option<String>, Option<String>) {
+    let clean = |raw: &str| {
+        let raw = raw.split('\t').next().unwrap_or_default().trim();
+        (raw != "/dev/null").then(|| normalize_path(raw))
+    };
+    let (old, new) = (clean(old), clean(new));
+    let prefixed = old.as_deref().is_none_or(|p| p.starts_with("a/"))
+        && new.as_deref().is_none_or(|p| p.starts_with("b/"));
+    if prefixed {
+        let strip = |p: Option<String>| p.map(|p| p[2..].to_string());
+        (strip(old), strip(new))
+    } else {
+        (old, new)
+    }
+}
+
+/// Parse `@@ -l,s +l,s @@` into the old start line and the old and new line
+/// counts. Counts default to 1 when omitted.
+fn parse_hunk_header(line: &str) -> Option<(usize, usize, usize)> {
+    let ranges = line.strip_prefix("@@ -")?;
+    let (old, rest) = ranges.split_once(" +")?;
+    let (new, _) = rest.split_once(" @@")?;
+    let range = |r: &str| -> Option<(usize, usize)> {
+        match r.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((r.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(old)?;
+    let (_, new_count) = range(new)?;
+    Some((old_start, old_count, new_count))
+}
+
+/// A path as it appears in `FileInfo::relative_path`.
+fn normalize_path(path: &str) -> String {
+    let path = normalize_separators(path.trim());
+    path.strip_prefix("./").map_or(path.clone(), str::to_string)
+}
+
+/// The file named by a Markdown heading like ``## `src/main.rs` ``. Labels
+/// after the path, such as `(new file)`, are ignored.
+fn heading_path(line: &str) -> Option<&str> {
+    let rest = line.trim_start_matches('#');
+    if rest.len() == line.len() {
+        return None;
+    }
+    let rest = rest.strip_prefix(' ')?.trim_start().strip_prefix('`')?;
+    let (path, _) = rest.split_once('`')?;
+    (!path.is_empty()).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(diff: &str) -> FilePatch {
+        let lines: Vec<&str> = diff.lines().collect();
+        parse_diff(&lines, None).pop().expect("one file patch")
+    }
+
+    fn apply(content: &str, diff: &str, fuzz: usize) -> Result<String, Vec<usize>> {
+        apply_hunks(content, &patch(diff).hunks, fuzz)
+    }
+
+    fn state(current: Option<&str>) -> FileState {
+        FileState {
+            path: "a.txt".to_string(),
+            target: PathBuf::from("a.txt"),
+            original: current.map(str::to_string),
+            current: current.map(str::to_string),
+            conflicts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn hunk_applies_where_the_header_says() {
+        let diff = "--- a/a.txt\n+++ b/a.txt\n@@ -2,3 +2,3 @@\n b\n-c\n+C\n d\n";
+        assert_eq!(
+            apply("a\nb\nc\nd\ne\n", diff, 0).unwrap(),
+            "a\nb\nC\nd\ne\n"
+        );
+    }
+
+    #[test]
+    fn hunk_is_found_at_an_offset() {
+        let diff = "--- a/a.txt\n+++ b/a.txt\n@@ -1,3 +1,3 @@\n b\n-c\n+C\n d\n";
+        let content = "x\ny\nz\na\nb\nc\nd\n";
+        assert_eq!(apply(content, diff, 0).unwrap(), "x\ny\nz\na\nb\nC\nd\n");
+    }
+
+    #[test]
+    fn later_hunks_follow_the_shift_of_earlier_ones() {
+        let diff = "--- a/a.txt\n+++ b/a.txt\n\
+                    @@ -1,2 +1,4 @@\n a\n+a1\n+a2\n b\n\
+                    @@ -5,2 +7,2 @@\n e\n-f\n+F\n";
+        let hunks = patch(diff).hunks;
+        let lines: Vec<String> = "a\nb\na1\na2\nc\nd\ne\nf\n"
+            .split_inclusive('\n')
+            .map(str::to_string)
+            .collect();
+        let (pos, front, _) = locate_hunk(&lines, &hunks[1], 2, 0).unwrap();
+        assert_eq!((pos, front), (6, 0));
+        assert_eq!(
+            apply("a\nb\nc\nd\ne\nf\n", diff, 0).unwrap(),
+            "a\na1\na2\nb\nc\nd\ne\nF\n"
+        );
+    }
+
+    #[test]
+    fn fuzz_drops_context_that_no_longer_matches() {
+        let diff = "--- a/a.txt\n+++ b/a.txt\n@@ -1,5 +1,5 @@\n old\n b\n-c\n+C\n d\n old\n";
+        let content = "new\nb\nc\nd\nnew\n";
+        assert_eq!(apply(content, diff, 0), Err(vec![1]));
+        assert_eq!(apply(content, diff, 1).unwrap(), "new\nb\nC\nd\nnew\n");
+    }
+
+    #[test]
+    fn mismatched_hunks_are_reported_by_number() {
+        let diff = "--- a/a.txt\n+++ b/a.txt\n\
+                    @@ -1,1 +1,1 @@\n-a\n+A\n\
+                    @@ -3,1 +3,1 @@\n-missing\n+M\n";
+        assert_eq!(apply("a\nb\nc\n", diff, 2), Err(vec![2]));
+    }
+
+    #[test]
+    fn crlf_line_endings_are_kept() {
+        let diff = "--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,3 @@\n a\n+x\n b\n";
+        assert_eq!(apply("a\r\nb\r\n", diff, 0).unwrap(), "a\r\nx\r\nb\r\n");
+    }
+
+    #[test]
+    fn a_missing_final_newline_stays_missing() {
+        let diff =
+            "--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n a\n-b\n+B\n\\ No newline at end of file\n";
+        assert_eq!(apply("a\nb", diff, 0).unwrap(), "a\nB");
+    }
+
+    #[test]
+    fn patches_create_and_delete_files() {
+        let create = patch("--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,2 @@\n+one\n+two\n");
+        assert!(create.creates && !create.deletes);
+        assert_eq!(create.path, "new.txt");
+        let mut new = state(None);
+        apply_change(&mut new, Change::Patch(create), 0);
+        assert_eq!(new.current.as_deref(), Some("one\ntwo\n"));
+
+        let delete = patch("--- a/old.txt\n+++ /dev/null\n@@ -1,1 +0,0 @@\n-gone\n");
+        assert!(delete.deletes && !delete.creates);
+        let mut old = state(Some("gone\n"));
+        apply_change(&mut old, Change::Patch(delete), 0);
+        assert_eq!(old.current, None);
+        assert!(old.conflicts.is_empty());
+    }
+
+    #[test]
+    fn creating_an_existing_file_conflicts() {
+        let create = patch("--- /dev/null\n+++ b/a.txt\n@@ -0,0 +1 @@\n+x\n");
+        let mut existing = state(Some("y\n"));
+        apply_change(&mut existing, Change::Patch(create), 0);
+        assert_eq!(existing.conflicts, ["file already exists"]);
+    }
+
+    #[test]
+    fn file_blocks_replace_whole_files() {
+        let response = "Here you go:\n\n### `src/lib.rs`\n\n```rust\nfn main() {}\n```\n";
+        let changes = parse_response(response).unwrap();
+        assert!(matches!(
+            changes.as_slice(),
+            [Change::Replace { path, content }] if path == "src/lib.rs" && content == "fn main() {}\n"
+        ));
+    }
+
+    #[test]
+    fn an_unclosed_file_block_is_rejected() {
+        let response = "### `src/lib.rs`\n\n```rust\nfn main() {\n";
+        assert_eq!(
+            parse_response(response).err().as_deref(),
+            Some("The code block of `src/lib.rs` is not closed")
+        );
+    }
+
+    #[test]
+    fn paths_lose_diff_prefixes_and_backslashes() {
+        assert_eq!(
+            diff_paths("a/src/lib.rs\t2024-01-01", "b/src/lib.rs"),
+            (
+                Some("src/lib.rs".to_string()),
+                Some("src/lib.rs".to_string())
+            )
+        );
+        assert_eq!(
+            diff_paths("/dev/null", "b/new.rs"),
+            (None, Some("new.rs".to_string()))
+        );
+        // Only stripped when both sides carry them
+        assert_eq!(
+            diff_paths("a/x.rs", "x.rs"),
+            (Some("a/x.rs".to_string()), Some("x.rs".to_string()))
+        );
+        assert_eq!(normalize_path(" ./src\\win\\main.rs "), "src/win/main.rs");
+    }
+
+    fn apply_response(content: &str, response: &str) -> FileState {
+        let mut file = state(Some(content));
+        for change in parse_response(response).unwrap() {
+            apply_change(&mut file, change, 2);
+        }
+        file
+    }
+
+    #[test]
+    fn a_bullet_after_a_bare_diff_is_not_part_of_the_hunk() {
+        let response = "--- a/a.txt\n+++ b/a.txt\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n\
+                        - Note: this also changes nothing else.\n";
+        let file = apply_response("a\nb\nc\n", response);
+        assert!(file.conflicts.is_empty(), "{:?}", file.conflicts);
+        assert_eq!(file.current.as_deref(), Some("a\nB\nc\n"));
+    }
+
+    #[test]
+    fn an_indented_line_after_a_bare_diff_is_not_part_of_the_hunk() {
+        let response = "--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n-a\n+A\n b\n\
+                        \n   indented explanation\n";
+        let file = apply_response("a\nb\n", response);
+        assert!(file.conflicts.is_empty(), "{:?}", file.conflicts);
+        assert_eq!(file.current.as_deref(), Some("A\nb\n"));
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufWriter, Read, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+use crate::source::FileSource;
+use crate::{FileInfo, SkippedFile};
+
+/// Name of the manifest written at the root of every archive.
+pub const MANIFEST_NAME: &str = "MANIFEST";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ArchiveFormat {
+    /// Zip archive with deflate compression
+    Zip,
+    /// Gzip-compressed tarball
+    #[cfg_attr(feature = "cli", value(name = "tar.gz"))]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+}
+
+/// Outcome of writing an archive.
+pub struct ArchiveSummary {
+    pub files_processed: usize,
+    /// Paths skipped by the scan, listed in the manifest.
+    pub skipped: Vec<SkippedFile>,
+    /// Files that failed to read, listed after the paths skipped by the scan.
+    pub failed: Vec<SkippedFile>,
+}
+
+/// One line of the manifest.
+struct ManifestEntry<'a> {
+    relative_path: &'a str,
+    size: u64,
+    sha256: String,
+}
+
+/// Hashes and counts everything read through it, so a file is read only
+/// once while it is packed.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+}
+
+enum ArchiveWriter {
+    Zip(Box<ZipWriter<BufWriter<fs::File>>>),
+    TarGz(tar::Builder<GzEncoder<BufWriter<fs::File>>>),
+}
+
+impl ArchiveWriter {
+    fn create(path: &Path, format: ArchiveFormat) -> io::Result<Self> {
+        let file = BufWriter::new(fs::File::create(path)?);
+        Ok(match format {
+            ArchiveFormat::Zip => ArchiveWriter::Zip(Box::new(ZipWriter::new(file))),
+            ArchiveFormat::TarGz => ArchiveWriter::TarGz(tar::Builder::new(GzEncoder::new(
+                file,
+                Compression::default(),
+            ))),
+        })
+    }
+
+    /// Add an entry of exactly `size` bytes read from `data`.
+    fn add(&mut self, name: &str, data: &mut dyn Read, size: u64, mtime: u64) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => {
+                let options = SimpleFileOptions::default()
+                    .unix_permissions(0o644)
+                    .large_file(size > u64::from(u32::MAX));
+                zip.start_file(name, options).map_err(io::Error::other)?;
+                io::copy(data, zip.as_mut())?;
+            }
+            ArchiveWriter::TarGz(tar) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(size);
+                header.set_mode(0o644);
+                header.set_mtime(mtime);
+                tar.append_data(&mut header, name, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => zip.finish().map_err(io::Error::other)?.flush(),
+            ArchiveWriter::TarGz(tar) => tar.into_inner()?.finish()?.flush(),
+        }
+    }
+}
+
+/// Pack the selected files into an archive at `path`, keeping their relative
+/// paths, followed by a `MANIFEST` with the tree and each file's size and
+/// SHA-256 hash and the paths in `skipped`, with any files that fail to
+/// read.
+///
+/// # Errors
+///
+/// Fails if a file cannot be read or the archive cannot be written.
+pub fn write_archive(
+    source: &dyn FileSource,
+    files: &[FileInfo],
+    tree_structure: &str,
+    path: &Path,
+    format: ArchiveFormat,
+    mut skipped: Vec<SkippedFile>,
+) -> io::Result<ArchiveSummary> {
+    let mut archive = ArchiveWriter::create(path, format)?;
+    let mut entries = Vec::with_capacity(files.len());
+    let scanned = skipped.len();
+
+    for file_info in files {
+        // Only failures before anything is written can be skipped safely
+        let opened = source
+            .open(file_info)
+            .and_then(|mut content| Ok((content.seek(SeekFrom::End(0))?, content)));
+        let (size, mut content) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                skipped.push(SkippedFile {
+                    relative_path: file_info.relative_path.clone(),
+                    is_dir: false,
+                    error: e.into(),
+                });
+                continue;
+            }
+        };
+        content.seek(SeekFrom::Start(0))?;
+        let mtime = source.modified(file_info).map_or(0, unix_seconds);
+
+        // The entry size is fixed up front, so a file that grows meanwhile is cut
+        let mut reader = HashingReader {
+            inner: content.take(size),
+            hasher: Sha256::new(),
+            size: 0,
+        };
+        archive.add(&file_info.relative_path, &mut reader, size, mtime)?;
+        if reader.size != size {
+            return Err(io::Error::other(format!(
+                "{} changed while it was being packed",
+                file_info.relative_path
+            )));
+        }
+        entries.push(ManifestEntry {
+            relative_path: &file_info.relative_path,
+            size,
+            sha256: format!("{:x}", reader.hasher.finalize()),
+        });
+    }
+
+    let manifest = manifest(tree_structure, &entries, &skipped);
+    archive.add(
+        MANIFEST_NAME,
+        &mut manifest.as_bytes(),
+        manifest.len() as u64,
+        unix_seconds(SystemTime::now()),
+    )?;
+    archive.finish()?;
+
+    let files_processed = entries.len();
+    let failed = skipped.split_off(scanned);
+    Ok(ArchiveSummary {
+        files_processed,
+        skipped,
+        failed,
+    })
+}
+
+fn manifest(tree_structure: &str, entries: &[ManifestEntry], skipped: &[SkippedFile]) -> String {
+    let total: u64 = entries.iter().map(|e| e.size).sum();
+    let mut manifest = format!(
+        "# rosetree manifest\n\n## File Structure\n\n{tree_structure}\n## Files\n\n{} files, {total} bytes\n\n",
+        entries.len()
+    );
+    for entry in entries {
+        // Writing to a String cannot fail
+        let _ = writeln!(
+            manifest,
+            "{}  {:>10}  {}",
+            entry.sha256, entry.size, entry.relative_path
+        );
+    }
+    if !skipped.is_empty() {
+        manifest.push_str("\n## Skipped Files\n\n");
+        for skipped in skipped {
+            let slash = if skipped.is_dir { "/" } else { "" };
+            let _ = writeln!(
+                manifest,
+                "{}{slash}  ({})",
+                skipped.relative_path, skipped.error
+            );
+        }
+    }
+    manifest
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
@@ -0,0 +1,195 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use dashmap::DashMap;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Name of the per-directory git attributes file.
+pub const GITATTRIBUTES_FILE_NAME: &str = ".gitattributes";
+
+/// Files marked generated or vendored that are scanned anyway.
+#[derive(Clone, Copy, Default)]
+pub struct MarkedFiles {
+    pub generated: bool,
+    pub vendored: bool,
+}
+
+/// Why `.gitattributes` leave a file out of the scan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mark {
+    /// Marked `binary` or `-diff`.
+    Binary,
+    /// Marked `linguist-generated` and not included.
+    Generated,
+    /// Marked `linguist-vendored` and not included.
+    Vendored,
+}
+
+/// Attributes of one `.gitattributes` file that decide whether a file is
+/// scanned. Each is kept as a gitignore matcher where set attributes are
+/// plain patterns and unset ones are negated, so the last matching line wins
+/// as it does in git.
+struct AttributeFile {
+    generated: Gitignore,
+    vendored: Gitignore,
+    binary: Gitignore,
+}
+
+impl AttributeFile {
+    /// The attributes of the `.gitattributes` file in `dir`, together with
+    /// the errors of lines whose pattern is not a valid glob.
+    fn parse(dir: &Path, text: &str) -> (Self, Vec<ignore::Error>) {
+        let mut generated = GitignoreBuilder::new(dir);
+        let mut vendored = GitignoreBuilder::new(dir);
+        let mut binary = GitignoreBuilder::new(dir);
+        let mut errors = Vec::new();
+
+        for line in text.lines() {
+            let mut fields = line.split_whitespace();
+            // Macro definitions and negated patterns are not valid paths
+            let Some(pattern) = fields
+                .next()
+                .filter(|p| !p.starts_with(['#', '!']) && !p.starts_with("[attr]"))
+            else {
+                continue;
+            };
+            for attribute in fields {
+                let (builder, set) = match attribute {
+                    "linguist-generated" | "linguist-generated=true" => (&mut generated, true),
+                    "-linguist-generated" | "!linguist-generated" | "linguist-generated=false" => {
+                        (&mut generated, false)
+                    }
+                    "linguist-vendored" | "linguist-vendored=true" => (&mut vendored, true),
+                    "-linguist-vendored" | "!linguist-vendored" | "linguist-vendored=false" => {
+                        (&mut vendored, false)
+                    }
+                    "binary" | "-diff" => (&mut binary, true),
+                    _ if attribute == "diff" || attribute.starts_with("diff=") => {
+                        (&mut binary, false)
+                    }
+                    _ => continue,
+                };
+                let line = if set {
+                    pattern.to_string()
+                } else {
+                    format!("!{pattern}")
+                };
+                if let Err(err) = builder.add_line(None, &line) {
+                    errors.push(err);
+                }
+            }
+        }
+
+        let build =
+            |builder: GitignoreBuilder| builder.build().unwrap_or_else(|_| Gitignore::empty());
+        let file = AttributeFile {
+            generated: build(generated),
+            vendored: build(vendored),
+            binary: build(binary),
+        };
+        (file, errors)
+    }
+}
+
+/// `.gitattributes` files from the top of the repository down to each
+/// scanned file, loaded once per directory as the walk reaches it.
+pub struct GitAttributes<L> {
+    top: PathBuf,
+    load: L,
+    include: MarkedFiles,
+    files: DashMap<PathBuf, Option<Arc<AttributeFile>>>,
+    errors: Mutex<Vec<(PathBuf, ignore::Error)>>,
+}
+
+/// Attributes read from the working tree.
+pub type DiskAttributes = GitAttributes<fn(&Path) -> Option<String>>;
+
+impl DiskAttributes {
+    /// Attributes for a scan of `base_dir`, including the `.gitattributes`
+    /// files of its parents up to the repository root.
+    #[must_use]
+    pub fn on_disk(base_dir: &Path, include: MarkedFiles) -> Self {
+        let top = base_dir
+            .ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .unwrap_or(base_dir);
+        GitAttributes::new(top, include, |dir| {
+            fs::read_to_string(dir.join(GITATTRIBUTES_FILE_NAME)).ok()
+        })
+    }
+}
+
+impl<L: Fn(&Path) -> Option<String>> GitAttributes<L> {
+    /// `load` returns the contents of the `.gitattributes` file in a
+    /// directory at or below `top`, if it has one.
+    #[must_use]
+    pub fn new(top: &Path, include: MarkedFiles, load: L) -> Self {
+        GitAttributes {
+            top: top.to_path_buf(),
+            load,
+            include,
+            files: DashMap::new(),
+            errors: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Lines left out of the `.gitattributes` files loaded so far because
+    /// their pattern is invalid, each with the path of its file.
+    pub fn take_errors(&self) -> Vec<(PathBuf, ignore::Error)> {
+        std::mem::take(&mut *self.errors.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Why `path` is left out of the scan, if it is: marked `binary` or
+    /// `-diff`, or marked generated or vendored without being included.
+    pub fn skips(&self, path: &Path) -> Option<Mark> {
+        let files = self.files_for(path);
+        let is_set = |pick: fn(&AttributeFile) -> &Gitignore| {
+            // The deepest file with a matching line decides, as in git
+            files
+                .iter()
+                .rev()
+                .map(|file| pick(file).matched(path, false))
+                .find(|m| !m.is_none())
+                .is_some_and(|m| m.is_ignore())
+        };
+        if is_set(|f| &f.binary) {
+            Some(Mark::Binary)
+        } else if !self.include.generated && is_set(|f| &f.generated) {
+            Some(Mark::Generated)
+        } else if !self.include.vendored && is_set(|f| &f.vendored) {
+            Some(Mark::Vendored)
+        } else {
+            None
+        }
+    }
+
+    /// Attribute files that apply to `path`, outermost first.
+    fn files_for(&self, path: &Path) -> Vec<Arc<AttributeFile>> {
+        let Some(parent) = path.parent() else {
+            return Vec::new();
+        };
+        let mut files: Vec<_> = parent
+            .ancestors()
+            .take_while(|dir| dir.starts_with(&self.top))
+            .filter_map(|dir| {
+                self.files
+                    .entry(dir.to_path_buf())
+                    .or_insert_with(|| {
+                        (self.load)(dir).map(|text| {
+                            let (file, errors) = AttributeFile::parse(dir, &text);
+                            let path = dir.join(GITATTRIBUTES_FILE_NAME);
+                            self.errors
+                                .lock()
+                                .unwrap_or_else(PoisonError::into_inner)
+                                .extend(errors.into_iter().map(|err| (path.clone(), err)));
+                            Arc::new(file)
+                        })
+                    })
+                    .clone()
+            })
+            .collect();
+        files.reverse();
+        files
+    }
+}
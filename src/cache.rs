@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::UNIX_EPOCH;
+
+use dashmap::DashMap;
+use dashmap::mapref::one::RefMut;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Bumped whenever the layout of the cache file changes.
+const CACHE_VERSION: u32 = 1;
+
+/// Identifies a version of a file without reading it. Any difference means
+/// the cached facts no longer apply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    size: u64,
+    inode: u64,
+}
+
+impl Stamp {
+    fn of(metadata: &fs::Metadata) -> Self {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Stamp {
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+            size: metadata.len(),
+            inode: inode(metadata),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> u64 {
+    0
+}
+
+/// What is known about the content of a file that was read in full.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ContentStats {
+    /// Lines as counted in JSON reports; a last line without a newline counts.
+    pub lines: usize,
+    /// SHA-256 of the content, in lowercase hex.
+    pub sha256: String,
+    /// Token estimate by tokenizer name.
+    pub tokens: BTreeMap<String, usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    stamp: Stamp,
+    /// Verdict of the text check, or `None` if the file was never checked
+    /// because its extension is known to be text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content: Option<ContentStats>,
+    /// Looked up during this run, so known to still exist.
+    #[serde(skip)]
+    seen: bool,
+}
+
+impl Entry {
+    fn new(stamp: Stamp) -> Self {
+        Entry {
+            stamp,
+            text: None,
+            content: None,
+            seen: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    /// Release that wrote the cache; its text detection may differ from ours.
+    rosetree: String,
+    root: PathBuf,
+    files: HashMap<String, Entry>,
+}
+
+/// Facts about a project's files kept between runs, so a file that has not
+/// changed is neither reopened to tell whether it is text nor read again to
+/// count its tokens. Entries are keyed by relative path and checked against
+/// the file's modification time, size and inode before use.
+///
+/// Lookups and updates may come from several threads. Nothing reaches the
+/// disk until [`ScanCache::save`].
+pub struct ScanCache {
+    root: PathBuf,
+    path: Option<PathBuf>,
+    files: DashMap<String, Entry>,
+    dirty: AtomicBool,
+}
+
+impl ScanCache {
+    /// The cache saved for `root`, or an empty one if there is none or it
+    /// was written by another release of rosetree.
+    #[must_use]
+    pub fn load(root: &Path) -> Self {
+        let path = cache_path(root);
+        // A cache that cannot be read is rebuilt rather than reported
+        let files = path
+            .as_ref()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|bytes| serde_json::from_slice::<CacheFile>(&bytes).ok())
+            .filter(|cache| {
+                cache.version == CACHE_VERSION && cache.rosetree == env!("CARGO_PKG_VERSION")
+            })
+            .map(|cache| cache.files.into_iter().collect())
+            .unwrap_or_default();
+        ScanCache {
+            root: root.to_path_buf(),
+            path,
+            files,
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// The text verdict recorded for the file at `relative_path`, if it has
+    /// not changed since. A changed file loses everything recorded for it.
+    pub(crate) fn verdict(&self, relative_path: &str, metadata: &fs::Metadata) -> Option<bool> {
+        self.current(relative_path, metadata)?.text
+    }
+
+    pub(crate) fn record_verdict(&self, relative_path: &str, metadata: &fs::Metadata, text: bool) {
+        self.entry(relative_path, metadata).text = Some(text);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// The content facts recorded for the file at `relative_path`, if it has
+    /// not changed since.
+    #[must_use]
+    pub fn content(&self, relative_path: &str, metadata: &fs::Metadata) -> Option<ContentStats> {
+        self.current(relative_path, metadata)?.content.clone()
+    }
+
+    /// Record the line count and hash of `content` together with its token
+    /// estimate by `tokenizer`. `metadata` must have been taken before the
+    /// content was read, so a file changed in between is counted again on
+    /// the next run.
+    pub fn record_content(
+        &self,
+        relative_path: &str,
+        metadata: &fs::Metadata,
+        content: &[u8],
+        tokenizer: &str,
+        tokens: usize,
+    ) {
+        let mut entry = self.entry(relative_path, metadata);
+        let stats = entry.content.get_or_insert_with(|| ContentStats {
+            lines: content.split_inclusive(|&b| b == b'\n').count(),
+            sha256: format!("{:x}", Sha256::digest(content)),
+            tokens: BTreeMap::new(),
+        });
+        stats.tokens.insert(tokenizer.to_string(), tokens);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// The entry of an unchanged file, dropping the entry of a changed one.
+    fn current(
+        &self,
+        relative_path: &str,
+        metadata: &fs::Metadata,
+    ) -> Option<RefMut<'_, String, Entry>> {
+        let mut entry = self.files.get_mut(relative_path)?;
+        if entry.stamp != Stamp::of(metadata) {
+            drop(entry);
+            self.files.remove(relative_path);
+            self.dirty.store(true, Ordering::Relaxed);
+            return None;
+        }
+        entry.seen = true;
+        Some(entry)
+    }
+
+    /// The entry for the current version of a file, replacing a stale one.
+    fn entry(&self, relative_path: &str, metadata: &fs::Metadata) -> RefMut<'_, String, Entry> {
+        let stamp = Stamp::of(metadata);
+        let mut entry = self
+            .files
+            .entry(relative_path.to_string())
+            .or_insert_with(|| Entry::new(stamp));
+        if entry.stamp != stamp {
+            *entry = Entry::new(stamp);
+        }
+        entry.seen = true;
+        entry
+    }
+
+    /// Write the cache if anything changed, leaving out files that were not
+    /// looked up during this run and no longer exist.
+    ///
+    /// # Errors
+    ///
+    /// Fails if there is no cache directory or the cache cannot be written.
+    pub fn save(&self) -> io::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no cache directory"))?;
+        let files = self
+            .files
+            .iter()
+            .filter(|entry| entry.seen || self.root.join(entry.key()).exists())
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        let cache = CacheFile {
+            version: CACHE_VERSION,
+            rosetree: env!("CARGO_PKG_VERSION").to_string(),
+            root: self.root.clone(),
+            files,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Written aside and renamed, so a concurrent run never reads half a file
+        let partial = path.with_extension("json.partial");
+        fs::write(
+            &partial,
+            serde_json::to_vec(&cache).map_err(io::Error::other)?,
+        )?;
+        fs::rename(partial, path)
+    }
+
+    /// Remove the caches of every project. Returns the directory that held
+    /// them, or `None` if there was nothing to remove.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the cache directory exists but cannot be removed.
+    pub fn clear_all() -> io::Result<Option<PathBuf>> {
+        let Some(dir) = cache_dir() else {
+            return Ok(None);
+        };
+        match fs::remove_dir_all(&dir) {
+            Ok(()) => Ok(Some(dir)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// `$XDG_CACHE_HOME/rosetree/scans`, or the platform's cache directory.
+fn cache_dir() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join("rosetree").join("scans"))
+}
+
+/// `<cache dir>/<hash of root>.json`
+fn cache_path(root: &Path) -> Option<PathBuf> {
+    let hash = Sha256::digest(root.to_string_lossy().as_bytes());
+    Some(cache_dir()?.join(format!("{hash:x}.json")))
+}
@@ -0,0 +1,320 @@
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::render::markdown::{
+    NO_FINAL_NEWLINE, fence_for, file_footer, file_heading, write_skipped,
+};
+use crate::source::FileSource;
+use crate::tokens::Tokenizer;
+use crate::{FileInfo, REPORT_MARKER, SkippedFile};
+
+/// Maximum size of the file contents in each part of a split report.
+pub enum SplitLimit {
+    Bytes(usize),
+    Lines(usize),
+    Tokens(usize, Box<dyn Tokenizer>),
+}
+
+impl SplitLimit {
+    fn measure(&self, text: &str) -> usize {
+        match self {
+            SplitLimit::Bytes(_) => text.len(),
+            SplitLimit::Lines(_) => text.matches('\n').count(),
+            SplitLimit::Tokens(_, tokenizer) => tokenizer.count(text),
+        }
+    }
+
+    fn max(&self) -> usize {
+        match self {
+            SplitLimit::Bytes(max) | SplitLimit::Lines(max) | SplitLimit::Tokens(max, _) => *max,
+        }
+    }
+}
+
+/// Outcome of writing a split report.
+pub struct SplitSummary {
+    pub parts: Vec<PathBuf>,
+    pub files_processed: usize,
+    /// Paths skipped by the scan, listed at the end of the last part.
+    pub skipped: Vec<SkippedFile>,
+    /// Files that failed to read, listed after the paths skipped by the scan.
+    pub failed: Vec<SkippedFile>,
+}
+
+/// `report.md` becomes `report_part01.md`, `report_part02.md`, ...
+fn part_path(base: &Path, index: usize) -> PathBuf {
+    let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match base.extension() {
+        Some(ext) => format!("{stem}_part{index:02}.{}", ext.to_string_lossy()),
+        None => format!("{stem}_part{index:02}"),
+    };
+    base.with_file_name(name)
+}
+
+struct PartWriter<'a> {
+    base: &'a Path,
+    tree_structure: &'a str,
+    tree_once: bool,
+    parts: Vec<PathBuf>,
+    writer: Option<BufWriter<fs::File>>,
+    /// Size of the file contents written to the current part.
+    used: usize,
+}
+
+impl PartWriter<'_> {
+    /// Flush the current part and start the next one with its own header, so
+    /// every part can be read on its own.
+    fn next_part(&mut self) -> std::io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        let index = self.parts.len() + 1;
+        let path = part_path(self.base, index);
+        let mut writer = BufWriter::new(fs::File::create(&path)?);
+
+        write!(
+            writer,
+            "{REPORT_MARKER}\n# Project Analysis Report (part {index})\n\n"
+        )?;
+        if self.tree_once && index > 1 {
+            let first = part_path(self.base, 1);
+            let first_name = first.file_name().unwrap_or_default().to_string_lossy();
+            write!(
+                writer,
+                "## File Structure\n\nSee `{first_name}` for the file structure.\n\n"
+            )?;
+        } else {
+            write!(
+                writer,
+                "## File Structure\n\n```\n{}```\n\n",
+                self.tree_structure
+            )?;
+        }
+        write!(writer, "## File Contents\n\n")?;
+
+        self.parts.push(path);
+        self.writer = Some(writer);
+        self.used = 0;
+        Ok(())
+    }
+
+    /// Start a new part unless the current one is still empty.
+    fn ensure_room(&mut self) -> std::io::Result<()> {
+        if self.writer.is_none() || self.used > 0 {
+            self.next_part()?;
+        }
+        Ok(())
+    }
+
+    fn write_block(
+        &mut self,
+        heading: &str,
+        content: &str,
+        footer: &str,
+        cost: usize,
+    ) -> std::io::Result<()> {
+        let writer = self.writer.as_mut().expect("a part is open");
+        writer.write_all(heading.as_bytes())?;
+        writer.write_all(content.as_bytes())?;
+        let final_newline = content.is_empty() || content.ends_with('\n');
+        if !final_newline {
+            writeln!(writer)?;
+        }
+        writer.write_all(footer.as_bytes())?;
+        // Only the last block of a file can lack a final newline
+        if !final_newline {
+            write!(writer, "{NO_FINAL_NEWLINE}\n\n")?;
+        }
+        self.used += cost;
+        Ok(())
+    }
+}
+
+/// Cut `content` at line boundaries into pieces that each measure at most
+/// `budget`. A single line longer than the budget becomes its own piece.
+fn split_content<'a>(content: &'a str, budget: usize, limit: &SplitLimit) -> Vec<&'a str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    let mut used = 0;
+    for line in content.split_inclusive('\n') {
+        let cost = limit.measure(line);
+        if used + cost > budget && end > start {
+            pieces.push(&content[start..end]);
+            start = end;
+            used = 0;
+        }
+        end += line.len();
+        used += cost;
+    }
+    if end > start {
+        pieces.push(&content[start..end]);
+    }
+    pieces
+}
+
+/// Write the report as a series of part files next to `base`. Files are
+/// never split across parts unless a single file exceeds the limit, in which
+/// case it is written as labeled continuation blocks. Skipped paths, with
+/// any files that fail to read, are listed at the end of the last part.
+///
+/// # Errors
+///
+/// Fails if a file cannot be read or a part file cannot be written.
+pub fn write_files_chunked(
+    source: &dyn FileSource,
+    files: &[FileInfo],
+    tree_structure: &str,
+    base: &Path,
+    limit: &SplitLimit,
+    tree_once: bool,
+    mut skipped: Vec<SkippedFile>,
+) -> std::io::Result<SplitSummary> {
+    let mut parts = PartWriter {
+        base,
+        tree_structure,
+        tree_once,
+        parts: Vec::new(),
+        writer: None,
+        used: 0,
+    };
+    let mut files_processed = 0;
+    let scanned = skipped.len();
+
+    for file_info in files {
+        let content = match source.read_to_string(file_info) {
+            Ok(content) => content,
+            Err(e) => {
+                skipped.push(SkippedFile {
+                    relative_path: file_info.relative_path.clone(),
+                    is_dir: false,
+                    error: e.into(),
+                });
+                continue;
+            }
+        };
+
+        // One fence for the whole file keeps continuation blocks consistent
+        let fence = fence_for(&content);
+        let heading = file_heading(file_info, None, &fence);
+        let footer = file_footer(&fence);
+        let note = if content.is_empty() || content.ends_with('\n') {
+            String::new()
+        } else {
+            format!("{NO_FINAL_NEWLINE}\n\n")
+        };
+        let wrapper_cost = limit.measure(&heading) + limit.measure(&footer) + limit.measure(&note);
+        let cost = wrapper_cost + limit.measure(&content);
+
+        if cost <= limit.max() {
+            if parts.writer.is_none() || parts.used + cost > limit.max() {
+                parts.ensure_room()?;
+            }
+            parts.write_block(&heading, &content, &footer, cost)?;
+        } else {
+            let pieces = split_content(&content, limit.max().saturating_sub(wrapper_cost), limit);
+            let count = pieces.len();
+            for (i, piece) in pieces.into_iter().enumerate() {
+                parts.ensure_room()?;
+                let label = format!("{} of {count}", i + 1);
+                let heading = file_heading(file_info, Some(&label), &fence);
+                parts.write_block(
+                    &heading,
+                    piece,
+                    &footer,
+                    wrapper_cost + limit.measure(piece),
+                )?;
+            }
+        }
+        files_processed += 1;
+    }
+
+    if !skipped.is_empty() {
+        if parts.writer.is_none() {
+            parts.next_part()?;
+        }
+        if let Some(writer) = parts.writer.as_mut() {
+            write_skipped(writer, &skipped)?;
+        }
+    }
+    if let Some(mut writer) = parts.writer.take() {
+        writer.flush()?;
+    }
+    let failed = skipped.split_off(scanned);
+    Ok(SplitSummary {
+        parts: parts.parts,
+        files_processed,
+        skipped,
+        failed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::render::Content;
+
+    /// Every file holds the same text.
+    struct Same(&'static str);
+
+    impl FileSource for Same {
+        fn open(&self, _: &FileInfo) -> io::Result<Box<dyn Content + '_>> {
+            Ok(Box::new(io::Cursor::new(self.0.as_bytes())))
+        }
+
+        fn modified(&self, _: &FileInfo) -> Option<SystemTime> {
+            None
+        }
+    }
+
+    #[test]
+    fn a_line_longer_than_the_limit_is_its_own_piece() {
+        let limit = SplitLimit::Bytes(8);
+        let pieces = split_content("ab\ncdefghijklmn\nop\nqr\n", 8, &limit);
+        assert_eq!(pieces, ["ab\n", "cdefghijklmn\n", "op\nqr\n"]);
+        assert_eq!(split_content("", 8, &limit), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn a_missing_final_newline_is_noted_once_after_the_last_piece() {
+        let dir = std::env::temp_dir().join(format!("rosetree-chunk-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let files = [FileInfo {
+            path: PathBuf::from("a.txt"),
+            relative_path: "a.txt".to_string(),
+            extension: "txt".to_string(),
+            diff: None,
+        }];
+        let content = "line one\nline two\nline three\nline four\nlast line";
+        // The heading, fences and note take 7 of 9 lines, leaving 2 per piece
+        let summary = write_files_chunked(
+            &Same(content),
+            &files,
+            "a.txt\n",
+            &dir.join("report.md"),
+            &SplitLimit::Lines(9),
+            false,
+            Vec::new(),
+        )
+        .unwrap();
+
+        let parts: Vec<String> = summary
+            .parts
+            .iter()
+            .map(|part| fs::read_to_string(part).unwrap())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(summary.files_processed, 1);
+        assert_eq!(parts.len(), 2);
+        let (last, rest) = parts.split_last().unwrap();
+        assert!(rest.iter().all(|part| !part.contains(NO_FINAL_NEWLINE)));
+        assert!(last.contains("(2 of 2)"));
+        assert!(last.contains(&format!(
+            "line four\nlast line\n```\n\n{NO_FINAL_NEWLINE}\n"
+        )));
+    }
+}
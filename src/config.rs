@@ -0,0 +1,108 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use serde::Deserialize;
+
+use crate::render::xml::XmlOptions;
+use crate::{Error, Result};
+
+/// Name of the per-project configuration file, looked up in the scanned root.
+pub const CONFIG_FILE_NAME: &str = ".rosetree.toml";
+
+/// Name of the per-directory ignore file, using gitignore syntax.
+pub const RSTIGNORE_FILE_NAME: &str = ".rstignore";
+
+/// Output path, without extension, used when neither the command line nor
+/// the config sets one.
+pub const DEFAULT_OUTPUT_STEM: &str = "rosetree_{timestamp}";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Markdown document with fenced code blocks
+    #[default]
+    Markdown,
+    /// Single JSON document with the tree, file metadata and contents
+    Json,
+    /// One JSON record per file
+    Jsonl,
+    /// XML elements per file, for LLM prompts
+    Xml,
+    /// Self-contained HTML page with a clickable tree and highlighted code
+    Html,
+}
+
+impl OutputFormat {
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Markdown => "md",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Xml => "xml",
+            OutputFormat::Html => "html",
+        }
+    }
+}
+
+/// Project defaults read from `.rosetree.toml`. Every field is optional and
+/// command-line values take precedence field by field.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub gitignore: Option<bool>,
+    pub extensions: Vec<String>,
+    pub all: bool,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub output: Option<String>,
+    pub format: Option<OutputFormat>,
+    pub xml: XmlOptions,
+}
+
+impl Config {
+    /// Load `.rosetree.toml` from `root`, returning the defaults when the file
+    /// does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file cannot be read, does not parse, or sets invalid
+    /// values.
+    pub fn load(root: &Path) -> Result<Self> {
+        let path = root.join(CONFIG_FILE_NAME);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(source) => return Err(Error::Io { path, source }),
+        };
+        let config: Self = match toml::from_str(&contents) {
+            Ok(config) => config,
+            Err(e) => {
+                return Err(Error::Config {
+                    path,
+                    message: e.to_string(),
+                });
+            }
+        };
+        if let Err(message) = config.xml.validate() {
+            return Err(Error::Config { path, message });
+        }
+        Ok(config)
+    }
+}
+
+/// Expand `{timestamp}` and `{project}` placeholders in an output path template.
+#[must_use]
+pub fn expand_output_template(template: &str, root: &Path) -> PathBuf {
+    let timestamp_str = Local::now().format("%Y%m%d_%H%M%S").to_string();
+    let project = root
+        .file_name()
+        .map_or_else(|| "project".into(), |n| n.to_string_lossy());
+    PathBuf::from(
+        template
+            .replace("{timestamp}", &timestamp_str)
+            .replace("{project}", &project),
+    )
+}
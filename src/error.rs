@@ -0,0 +1,134 @@
+use std::io;
+use std::path::PathBuf;
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+/// Errors that stop a scan or a report.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A path could not be read.
+    #[error("Failed to read {}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+
+    /// The root to scan is not a directory.
+    #[error("Not a directory: {}", .0.display())]
+    NotADirectory(PathBuf),
+
+    /// An include or exclude glob could not be parsed.
+    #[error("Invalid glob pattern: {0}")]
+    Glob(#[from] ignore::Error),
+
+    /// The project config could not be parsed or holds invalid values.
+    #[error("Invalid {}: {message}", path.display())]
+    Config { path: PathBuf, message: String },
+
+    /// The git repository or a revision in it could not be read.
+    #[error("{0}")]
+    Git(String),
+
+    /// The report could not be written.
+    #[error("Failed to write the report: {0}")]
+    Write(#[from] io::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Why a file was left out of a scan or could not be read for a report.
+#[derive(Debug, thiserror::Error)]
+pub enum FileError {
+    #[error("permission denied")]
+    PermissionDenied,
+
+    /// The file is binary or in another encoding.
+    #[error("not UTF-8 text")]
+    NotUtf8,
+
+    /// The file is larger than the `--max-file-size` limit.
+    #[error("{size} bytes, over the limit of {limit}")]
+    TooLarge { size: u64, limit: u64 },
+
+    /// A symbolic link resolves to itself or to one of its own parents.
+    #[error("symlink loop")]
+    SymlinkLoop,
+
+    /// The file is marked `binary` or `-diff` in `.gitattributes`.
+    #[error("marked binary in .gitattributes")]
+    MarkedBinary,
+
+    #[error("{0}")]
+    Read(io::Error),
+}
+
+impl FileError {
+    /// Stable name of the reason, for machine-readable summaries.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            FileError::PermissionDenied => "permission_denied",
+            FileError::NotUtf8 => "not_utf8",
+            FileError::TooLarge { .. } => "too_large",
+            FileError::SymlinkLoop => "symlink_loop",
+            FileError::MarkedBinary => "marked_binary",
+            FileError::Read(_) => "read_failed",
+        }
+    }
+
+    /// Whether the path could not be read, as opposed to being left out for
+    /// its content or size.
+    #[must_use]
+    pub fn is_read_failure(&self) -> bool {
+        matches!(
+            self,
+            FileError::PermissionDenied | FileError::SymlinkLoop | FileError::Read(_)
+        )
+    }
+}
+
+impl From<io::Error> for FileError {
+    fn from(err: io::Error) -> Self {
+        #[cfg(unix)]
+        if err.raw_os_error() == Some(libc::ELOOP) {
+            return FileError::SymlinkLoop;
+        }
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => FileError::PermissionDenied,
+            // What `read_to_string` returns for content that is not UTF-8
+            io::ErrorKind::InvalidData => FileError::NotUtf8,
+            _ => FileError::Read(err),
+        }
+    }
+}
+
+impl From<ignore::Error> for FileError {
+    fn from(err: ignore::Error) -> Self {
+        match err {
+            ignore::Error::WithDepth { err, .. }
+            | ignore::Error::WithPath { err, .. }
+            | ignore::Error::WithLineNumber { err, .. } => FileError::from(*err),
+            ignore::Error::Loop { .. } => FileError::SymlinkLoop,
+            ignore::Error::Io(err) => FileError::from(err),
+            err => FileError::Read(io::Error::other(err)),
+        }
+    }
+}
+
+/// A path that was left out of a report, and why.
+#[derive(Debug)]
+pub struct SkippedFile {
+    /// Path relative to the scanned root, with `/` separators.
+    pub relative_path: String,
+    /// Set for directories that could not be walked.
+    pub is_dir: bool,
+    pub error: FileError,
+}
+
+impl Serialize for SkippedFile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut record = serializer.serialize_struct("SkippedFile", 4)?;
+        record.serialize_field("relative_path", &self.relative_path)?;
+        record.serialize_field("is_dir", &self.is_dir)?;
+        record.serialize_field("reason", self.error.code())?;
+        record.serialize_field("message", &self.error.to_string())?;
+        record.end()
+    }
+}
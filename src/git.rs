@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use git2::{
+    Delta, Diff, DiffFormat, DiffOptions, ObjectType, Oid, Repository, RevparseMode, Tree,
+    TreeWalkMode, TreeWalkResult,
+};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::overrides::Override;
+
+use crate::attributes::{GITATTRIBUTES_FILE_NAME, GitAttributes, Mark, MarkedFiles};
+use crate::config::RSTIGNORE_FILE_NAME;
+use crate::render::Content;
+use crate::source::FileSource;
+use crate::text::{is_known_text_extension, is_text_sample};
+use crate::{Error, FileError, FileInfo, Result, SkippedFile, normalize_separators};
+
+/// Git file mode of a symbolic link.
+const FILEMODE_LINK: i32 = 0o120_000;
+
+/// Which changes limit the scan to the files they touch.
+pub enum ChangeSet {
+    /// Working tree and index against `HEAD`, including untracked files.
+    Changed,
+    /// Index against `HEAD`.
+    Staged,
+    /// Working tree and index against a revision.
+    Since(String),
+    /// One revision against another, as `REV1..REV2` or `REV1...REV2`.
+    Range(String),
+}
+
+/// Files touched by a change set, keyed by their path relative to the
+/// scanned root, with their unified diff when requested. Deleted files are
+/// left out since there is nothing left to read.
+pub type ChangedFiles = HashMap<String, Option<Arc<str>>>;
+
+/// Compare in-process through libgit2 rather than by running `git`, reading
+/// the object database of the repository that contains `root`.
+///
+/// # Errors
+///
+/// Fails if `root` is not inside a git repository or a revision of
+/// `changes` cannot be resolved.
+pub fn changed_files(root: &Path, changes: &ChangeSet, with_diffs: bool) -> Result<ChangedFiles> {
+    let (repo, prefix) = open_repository(root)?;
+
+    let mut options = DiffOptions::new();
+    if !prefix.is_empty() {
+        options.pathspec(&prefix);
+    }
+    let diff = match changes {
+        ChangeSet::Changed => {
+            options
+                .include_untracked(true)
+                .recurse_untracked_dirs(true)
+                .show_untracked_content(true);
+            let head = head_tree(&repo)?;
+            repo.diff_tree_to_workdir_with_index(head.as_ref(), Some(&mut options))
+        }
+        ChangeSet::Staged => {
+            let head = head_tree(&repo)?;
+            repo.diff_tree_to_index(head.as_ref(), None, Some(&mut options))
+        }
+        ChangeSet::Since(rev) => {
+            let tree = rev_tree(&repo, rev)?;
+            repo.diff_tree_to_workdir_with_index(Some(&tree), Some(&mut options))
+        }
+        ChangeSet::Range(range) => {
+            let (old, new) = range_trees(&repo, range)?;
+            repo.diff_tree_to_tree(Some(&old), Some(&new), Some(&mut options))
+        }
+    }
+    .map_err(|e| Error::Git(format!("Failed to compare git trees: {}", e.message())))?;
+
+    collect_changes(&diff, &prefix, with_diffs)
+        .map_err(|e| Error::Git(format!("Failed to read git diff: {}", e.message())))
+}
+
+/// Open the repository containing `root`, along with the path of `root`
+/// inside its working tree (empty at the top level).
+fn open_repository(root: &Path) -> Result<(Repository, String)> {
+    let repo = Repository::discover(root)
+        .map_err(|e| Error::Git(format!("Not inside a git repository: {}", e.message())))?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::Git("The git repository has no working tree".to_string()))?
+        .canonicalize()
+        .map_err(|e| Error::Git(format!("Unable to access the git working tree: {e}")))?;
+    let prefix = root
+        .canonicalize()
+        .ok()
+        .and_then(|root| {
+            root.strip_prefix(&workdir)
+                .ok()
+                .map(|p| normalize_separators(&p.to_string_lossy()))
+        })
+        .unwrap_or_default();
+
+    Ok((repo, prefix))
+}
+
+/// Blob contents of one git revision, read from the object database.
+/// Several threads may read at once, each through its own handle.
+pub struct GitSource {
+    /// The repository's git directory, where more handles are opened.
+    git_dir: PathBuf,
+    // libgit2 handles may move between threads but not be shared, so a
+    // reader takes an idle one, or opens another when none is left
+    repos: Mutex<Vec<Repository>>,
+    blobs: HashMap<String, Oid>,
+    commit_time: Option<SystemTime>,
+}
+
+impl FileSource for GitSource {
+    fn open(&self, file_info: &FileInfo) -> io::Result<Box<dyn Content + '_>> {
+        let oid = self.blobs.get(&file_info.relative_path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "not in the scanned revision")
+        })?;
+        let idle = self
+            .repos
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop();
+        let repo = match idle {
+            Some(repo) => repo,
+            None => Repository::open(&self.git_dir).map_err(io::Error::other)?,
+        };
+        let content = repo
+            .find_blob(*oid)
+            .map(|blob| blob.content().to_vec())
+            .map_err(io::Error::other);
+        self.repos
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(repo);
+        Ok(Box::new(io::Cursor::new(content?)))
+    }
+
+    fn modified(&self, _file_info: &FileInfo) -> Option<SystemTime> {
+        self.commit_time
+    }
+}
+
+/// List the text files tracked at `rev` below `root`, applying the
+/// `--include`/`--exclude` globs and the `.gitattributes` of that revision,
+/// and return them with a source that reads their contents from it. Blobs
+/// that are not text or are larger than `max_file_size` are returned as
+/// skipped.
+///
+/// # Errors
+///
+/// Fails if `root` is not inside a git repository or `rev` does not name a
+/// commit.
+pub fn collect_files_at_rev(
+    root: &Path,
+    rev: &str,
+    overrides: &Override,
+    include: MarkedFiles,
+    max_file_size: Option<u64>,
+) -> Result<(Vec<FileInfo>, Vec<SkippedFile>, GitSource)> {
+    let (repo, prefix) = open_repository(root)?;
+    let tree = rev_tree(&repo, rev)?;
+    let top = root
+        .ancestors()
+        .nth(Path::new(&prefix).components().count())
+        .unwrap_or(root);
+    let read_file = |dir: &Path, name: &str| {
+        let path = dir.strip_prefix(top).ok()?.join(name);
+        let blob = tree
+            .get_path(&path)
+            .ok()?
+            .to_object(&repo)
+            .ok()?
+            .peel_to_blob()
+            .ok()?;
+        Some(String::from_utf8_lossy(blob.content()).into_owned())
+    };
+    let attributes =
+        GitAttributes::new(top, include, |dir| read_file(dir, GITATTRIBUTES_FILE_NAME));
+
+    let mut files = Vec::new();
+    let mut skipped = Vec::new();
+    let mut blobs = HashMap::new();
+    // `.rstignore` rules of each directory at `rev`, read when first needed
+    let mut rstignores: HashMap<PathBuf, Option<Gitignore>> = HashMap::new();
+    let commit_time = walk_rev(&repo, &prefix, rev, |relative_path, is_dir, oid| {
+        let path = root.join(&relative_path);
+        if overrides.matched(&path, is_dir).is_ignore() {
+            return false;
+        }
+        let read_rstignore = |dir: &Path| read_file(dir, RSTIGNORE_FILE_NAME);
+        if is_rstignored(
+            &path,
+            is_dir,
+            root,
+            &mut rstignores,
+            read_rstignore,
+            &mut skipped,
+        ) {
+            return false;
+        }
+        if is_dir {
+            return true;
+        }
+        let checked = match attributes.skips(&path) {
+            Some(Mark::Binary) => Err(FileError::MarkedBinary),
+            Some(Mark::Generated | Mark::Vendored) => return true,
+            None => check_blob(&repo, &path, oid, max_file_size),
+        };
+        if let Err(error) = checked {
+            skipped.push(SkippedFile {
+                relative_path,
+                is_dir: false,
+                error,
+            });
+        } else {
+            let extension = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or("")
+                .to_string();
+            blobs.insert(relative_path.clone(), oid);
+            files.push(FileInfo {
+                path,
+                relative_path,
+                extension,
+                diff: None,
+            });
+        }
+        true
+    })?;
+    // Invalid lines are recorded against their file and left out, as on disk
+    skipped.extend(
+        attributes
+            .take_errors()
+            .into_iter()
+            .map(|(path, err)| SkippedFile {
+                relative_path: crate::relative_path(&path, root),
+                is_dir: false,
+                error: err.into(),
+            }),
+    );
+    drop(attributes);
+    drop(tree);
+
+    let source = GitSource {
+        git_dir: repo.path().to_path_buf(),
+        repos: Mutex::new(vec![repo]),
+        blobs,
+        commit_time,
+    };
+    Ok((files, skipped, source))
+}
+
+/// Whether the `.rstignore` files at the revision leave out `path`. The
+/// deepest one with a matching rule decides, as on disk. Each directory's
+/// rules are read with `read_rstignore` when first needed and kept in
+/// `rstignores`; invalid lines are recorded in `skipped` and left out.
+fn is_rstignored(
+    path: &Path,
+    is_dir: bool,
+    root: &Path,
+    rstignores: &mut HashMap<PathBuf, Option<Gitignore>>,
+    read_rstignore: impl Fn(&Path) -> Option<String>,
+    skipped: &mut Vec<SkippedFile>,
+) -> bool {
+    path.ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(root))
+        .find_map(|dir| {
+            let matcher = rstignores.entry(dir.to_path_buf()).or_insert_with(|| {
+                let rules = read_rstignore(dir)?;
+                let mut builder = GitignoreBuilder::new(dir);
+                for line in rules.lines() {
+                    if let Err(err) = builder.add_line(None, line) {
+                        skipped.push(SkippedFile {
+                            relative_path: crate::relative_path(
+                                &dir.join(RSTIGNORE_FILE_NAME),
+                                root,
+                            ),
+                            is_dir: false,
+                            error: err.into(),
+                        });
+                    }
+                }
+                builder.build().ok()
+            });
+            let found = matcher.as_ref()?.matched(path, is_dir);
+            (!found.is_none()).then(|| found.is_ignore())
+        })
+        .unwrap_or(false)
+}
+
+/// Visit every directory and regular file below `prefix` at `rev` with its
+/// path relative to `prefix`. Returning `false` for a directory skips it.
+/// Returns the commit time of `rev` when it names a commit.
+fn walk_rev(
+    repo: &Repository,
+    prefix: &str,
+    rev: &str,
+    mut visit: impl FnMut(String, bool, Oid) -> bool,
+) -> Result<Option<SystemTime>> {
+    let object = repo
+        .revparse_single(rev)
+        .map_err(|e| Error::Git(format!("Unknown revision {rev:?}: {}", e.message())))?;
+    let commit_time = object.peel_to_commit().ok().map(|commit| {
+        UNIX_EPOCH + Duration::from_secs(commit.time().seconds().try_into().unwrap_or_default())
+    });
+    let mut tree = rev_tree(repo, rev)?;
+    if !prefix.is_empty() {
+        tree = tree
+            .get_path(Path::new(prefix))
+            .and_then(|entry| entry.to_object(repo))
+            .and_then(|object| object.peel_to_tree())
+            .map_err(|_| Error::Git(format!("{prefix} does not exist at {rev}")))?;
+    }
+
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        let Some(name) = entry.name() else {
+            return TreeWalkResult::Skip;
+        };
+        let relative_path = format!("{dir}{name}");
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                if visit(relative_path, true, entry.id()) {
+                    TreeWalkResult::Ok
+                } else {
+                    TreeWalkResult::Skip
+                }
+            }
+            // Symlinks and submodules have no content of their own
+            Some(ObjectType::Blob) if entry.filemode() != FILEMODE_LINK => {
+                visit(relative_path, false, entry.id());
+                TreeWalkResult::Ok
+            }
+            _ => TreeWalkResult::Ok,
+        }
+    })
+    .map_err(|e| Error::Git(format!("Failed to read {rev}: {}", e.message())))?;
+    Ok(commit_time)
+}
+
+/// The blob counterpart of `check_utf8_file`, plus the size limit.
+fn check_blob(
+    repo: &Repository,
+    path: &Path,
+    oid: Oid,
+    max_file_size: Option<u64>,
+) -> Result<(), FileError> {
+    let read_failed = |e: git2::Error| FileError::Read(io::Error::other(e));
+    if let Some(limit) = max_file_size {
+        let (size, _) = repo
+            .odb()
+            .and_then(|odb| odb.read_header(oid))
+            .map_err(read_failed)?;
+        let size = size as u64;
+        if size > limit {
+            return Err(FileError::TooLarge { size, limit });
+        }
+    }
+    if is_known_text_extension(path) {
+        return Ok(());
+    }
+    let blob = repo.find_blob(oid).map_err(read_failed)?;
+    let content = blob.content();
+    if is_text_sample(&content[..content.len().min(8192)]) {
+        Ok(())
+    } else {
+        Err(FileError::NotUtf8)
+    }
+}
+
+fn collect_changes(
+    diff: &Diff,
+    prefix: &str,
+    with_diffs: bool,
+) -> Result<ChangedFiles, git2::Error> {
+    // Paths in the diff are relative to the repository root
+    let relative = |path: Option<&Path>| {
+        let path = normalize_separators(&path?.to_string_lossy());
+        if prefix.is_empty() {
+            Some(path)
+        } else {
+            path.strip_prefix(prefix)?
+                .strip_prefix('/')
+                .map(str::to_string)
+        }
+    };
+
+    let mut texts: HashMap<String, String> = HashMap::new();
+    for delta in diff.deltas() {
+        if delta.status() != Delta::Deleted
+            && let Some(path) = relative(delta.new_file().path())
+        {
+            texts.insert(path, String::new());
+        }
+    }
+    if with_diffs {
+        diff.print(DiffFormat::Patch, |delta, _hunk, line| {
+            if let Some(text) = relative(delta.new_file().path()).and_then(|p| texts.get_mut(&p)) {
+                if matches!(line.origin(), '+' | '-' | ' ') {
+                    text.push(line.origin());
+                }
+                text.push_str(&String::from_utf8_lossy(line.content()));
+            }
+            true
+        })?;
+    }
+    Ok(texts
+        .into_iter()
+        .map(|(path, text)| (path, with_diffs.then(|| Arc::from(text))))
+        .collect())
+}
+
+/// `None` before the first commit, so everything counts as added.
+fn head_tree(repo: &Repository) -> Result<Option<Tree<'_>>> {
+    match repo.head() {
+        Ok(head) => head
+            .peel_to_tree()
+            .map(Some)
+            .map_err(|e| Error::Git(format!("Failed to read HEAD: {}", e.message()))),
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => Ok(None),
+        Err(e) => Err(Error::Git(format!("Failed to read HEAD: {}", e.message()))),
+    }
+}
+
+fn rev_tree<'r>(repo: &'r Repository, rev: &str) -> Result<Tree<'r>> {
+    repo.revparse_single(rev)
+        .and_then(|object| object.peel_to_tree())
+        .map_err(|e| Error::Git(format!("Unknown revision {rev:?}: {}", e.message())))
+}
+
+/// Trees on both sides of a range. `REV1...REV2` compares against the merge
+/// base of both revisions, like `git diff` does.
+fn range_trees<'r>(repo: &'r Repository, range: &str) -> Result<(Tree<'r>, Tree<'r>)> {
+    let invalid =
+        |message: &str| Error::Git(format!("Invalid revision range {range:?}: {message}"));
+    let spec = repo.revparse(range).map_err(|e| invalid(e.message()))?;
+    if !spec.mode().contains(RevparseMode::RANGE) {
+        return Err(invalid("expected REV1..REV2"));
+    }
+    let (Some(from), Some(to)) = (spec.from(), spec.to()) else {
+        return Err(invalid("expected REV1..REV2"));
+    };
+    let peel = |object: &git2::Object<'r>| object.peel_to_tree().map_err(|e| invalid(e.message()));
+    let new = peel(to)?;
+    let old = if spec.mode().contains(RevparseMode::MERGE_BASE) {
+        let base = repo
+            .merge_base(from.id(), to.id())
+            .map_err(|e| invalid(e.message()))?;
+        repo.find_commit(base)
+            .and_then(|commit| commit.tree())
+            .map_err(|e| invalid(e.message()))?
+    } else {
+        peel(from)?
+    };
+    Ok((old, new))
+}
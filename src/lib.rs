@@ -0,0 +1,124 @@
+//! Scan a directory, select files and render their contents into a single
+//! report for reading or for an LLM prompt.
+//!
+//! A [`Scanner`] walks the tree and yields [`FileInfo`]s, a [`Selector`]
+//! narrows them down, and [`write_report`] streams the chosen files through
+//! a [`Renderer`]. Markdown is the default [`OutputFormat`].
+//!
+//! ```no_run
+//! use rosetree::{OutputFormat, RenderOptions, Scanner, Selector};
+//!
+//! # fn main() -> Result<(), rosetree::Error> {
+//! let scan = Scanner::new(".").exclude("target/**").scan()?;
+//! let selector = Selector::extensions(["rs", "toml"]);
+//! let files = selector.select(scan.files);
+//! let tree = rosetree::generate_tree_structure(&files);
+//! let summary = rosetree::write_report(
+//!     std::io::stdout().lock(),
+//!     scan.source.as_ref(),
+//!     &files,
+//!     &tree,
+//!     OutputFormat::default(),
+//!     &RenderOptions::default(),
+//!     None,
+//!     selector.select_skipped(scan.skipped),
+//! )?;
+//! eprintln!("{} files written", summary.files_processed);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod archive;
+pub mod attributes;
+pub mod cache;
+pub mod chunk;
+pub mod config;
+mod error;
+pub mod git;
+mod read_ahead;
+pub mod render;
+mod report;
+mod scan;
+mod select;
+pub mod source;
+pub mod text;
+pub mod tokens;
+mod tree;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub use config::OutputFormat;
+pub use error::{Error, FileError, Result, SkippedFile};
+pub use read_ahead::READ_AHEAD_FILES;
+pub use render::{RenderOptions, Renderer};
+pub use report::{
+    MAX_BUFFERED_FILE, ReportSummary, TokenBudget, plan_tokens, write_report, write_report_with,
+};
+pub use scan::{GitIgnoreInfo, Scan, Scanner, Walk};
+pub use select::{Selector, available_extensions};
+pub use tree::{build_path_map, generate_tree_structure};
+
+/// First line of Markdown and HTML reports, used to recognise earlier
+/// reports when scanning.
+pub const REPORT_MARKER: &str = "<!-- Generated by rosetree -->";
+
+/// Start of JSON reports and of the header record of JSON Lines reports.
+pub const JSON_REPORT_PREFIX: &str = "{\"generator\":\"rosetree\"";
+
+/// A file found by a scan.
+#[derive(Clone, Debug)]
+pub struct FileInfo {
+    /// Location of the file below the scanned root.
+    pub path: PathBuf,
+    /// Path relative to the scanned root, with `/` separators.
+    pub relative_path: String,
+    /// Extension without the leading dot, empty if the file has none.
+    pub extension: String,
+    /// Unified diff of the file when the scan is limited to git changes.
+    pub diff: Option<Arc<str>>,
+}
+
+/// Path of `path` below `base_dir` as stored in `FileInfo::relative_path`.
+#[must_use]
+pub fn relative_path(path: &Path, base_dir: &Path) -> String {
+    normalize_separators(
+        &path
+            .strip_prefix(base_dir)
+            .unwrap_or(path)
+            .to_string_lossy(),
+    )
+}
+
+/// Use `/` as the path separator on every platform.
+#[must_use]
+pub fn normalize_separators(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+/// Code block language for a file extension, empty when there is none.
+#[must_use]
+pub fn get_language_from_extension(extension: &str) -> &'static str {
+    match extension {
+        "rs" => "rust",
+        "js" => "javascript",
+        "ts" => "typescript",
+        "py" => "python",
+        "go" => "go",
+        "java" => "java",
+        "c" | "h" | "hpp" => "c",
+        "cpp" | "cc" | "cxx" => "cpp",
+        "html" => "html",
+        "css" => "css",
+        "json" => "json",
+        "xml" => "xml",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "md" => "markdown",
+        "sh" => "bash",
+        "sql" => "sql",
+        "dockerfile" => "dockerfile",
+        "makefile" => "makefile",
+        _ => "", // No syntax highlighting
+    }
+}
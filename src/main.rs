@@ -1,32 +1,39 @@
-use std::collections::{BTreeSet, HashMap, HashSet};
+mod apply;
+mod picker;
+mod selection;
+mod unpack;
+
+use std::collections::HashSet;
 use std::fs;
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::io::{self, BufWriter, IsTerminal, Write};
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::Arc;
 use std::time::Instant;
 
-use chrono::Local;
-use content_inspector::inspect;
-use dashmap::DashMap;
-use ignore::WalkBuilder;
-use rayon::prelude::*;
-
-#[derive(Clone)]
-struct FileInfo {
-    path: PathBuf,
-    relative_path: String,
-    extension: String,
-}
-
-#[derive(Clone, Debug)]
-struct GitIgnoreInfo {
-    relative_path: String,
-}
+use clap::{Parser, Subcommand};
+
+use apply::ApplyArgs;
+use rosetree::archive::{ArchiveFormat, write_archive};
+use rosetree::attributes::MarkedFiles;
+use rosetree::cache::ScanCache;
+use rosetree::chunk::{SplitLimit, write_files_chunked};
+use rosetree::config::{Config, DEFAULT_OUTPUT_STEM, OutputFormat, expand_output_template};
+use rosetree::git::ChangeSet;
+use rosetree::render::xml::XmlAttribute;
+use rosetree::render::{DiffDisplay, RenderOptions};
+use rosetree::tokens::{BudgetPolicy, Inclusion, TokenOptions, TokenPlan, TokenizerKind};
+use rosetree::{
+    FileInfo, Scanner, Selector, SkippedFile, TokenBudget, available_extensions,
+    generate_tree_structure, plan_tokens, write_report,
+};
+use selection::Selection;
+use unpack::UnpackArgs;
 
 struct Timings {
-    find_gitignore: u128,
     collect_files: u128,
     read_contents: u128,
+    count_tokens: u128,
     generate_tree: u128,
     generate_output_string: u128,
     write_file: u128,
@@ -36,9 +43,9 @@ struct Timings {
 impl Timings {
     fn new() -> Self {
         Timings {
-            find_gitignore: 0,
             collect_files: 0,
             read_contents: 0,
+            count_tokens: 0,
             generate_tree: 0,
             generate_output_string: 0,
             write_file: 0,
@@ -47,618 +54,894 @@ impl Timings {
     }
 }
 
+/// Scan a directory tree and extract the contents of selected UTF-8 files
+/// into a Markdown report.
+///
+/// Values that are not given on the command line are taken from the project's
+/// `.rosetree.toml`, and otherwise asked for interactively when stdin is a
+/// terminal.
+#[derive(Parser)]
+#[command(name = "rst", version, about, args_conflicts_with_subcommands = true)]
+#[allow(clippy::struct_excessive_bools)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Directory to scan (defaults to the current directory)
+    root: Option<PathBuf>,
+
+    /// Apply .gitignore rules while scanning
+    #[arg(long, overrides_with = "no_gitignore")]
+    gitignore: bool,
+
+    /// Scan every file, ignoring .gitignore rules
+    #[arg(long = "no-gitignore", overrides_with = "gitignore")]
+    no_gitignore: bool,
+
+    /// Comma-separated file extensions to extract (e.g. `rs,toml`)
+    #[arg(
+        long,
+        value_name = "EXTS",
+        value_delimiter = ',',
+        conflicts_with = "all"
+    )]
+    ext: Vec<String>,
+
+    /// Extract every UTF-8 file type found
+    #[arg(long)]
+    all: bool,
+
+    /// Reuse the gitignore answer, extensions and globs chosen in the last
+    /// interactive run in this directory
+    #[arg(long)]
+    last: bool,
+
+    /// Choose files in an interactive tree with search and a preview
+    /// instead of by extension
+    #[arg(long)]
+    pick: bool,
+
+    /// Output file, or `-` for stdout. `{timestamp}` and `{project}` are
+    /// expanded (defaults to `rosetree_{timestamp}` plus the format's extension)
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<String>,
+
+    /// Report format
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// Pack the selected files into an archive with a MANIFEST instead of
+    /// writing a report
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        conflicts_with_all = ["format", "split", "max_tokens"]
+    )]
+    archive: Option<ArchiveFormat>,
+
+    /// Only extract files matching this glob (repeatable, gitignore syntax)
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files and directories matching this glob (repeatable, gitignore syntax)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Include files marked `linguist-generated` in .gitattributes
+    #[arg(long)]
+    include_generated: bool,
+
+    /// Include files marked `linguist-vendored` in .gitattributes
+    #[arg(long)]
+    include_vendored: bool,
+
+    /// Read files from this commit, branch or tag instead of the working
+    /// tree. Only tracked files are scanned
+    #[arg(long, value_name = "REV", conflicts_with_all = ["changed", "staged", "since"])]
+    rev: Option<String>,
+
+    /// Only extract files changed in the working tree since HEAD, including
+    /// untracked files that are not ignored
+    #[arg(long, group = "git_changes")]
+    changed: bool,
+
+    /// Only extract files with staged changes
+    #[arg(long, group = "git_changes")]
+    staged: bool,
+
+    /// Only extract files changed in the working tree since this revision
+    #[arg(long, value_name = "REV", group = "git_changes")]
+    since: Option<String>,
+
+    /// Only extract files changed between two revisions (`REV1..REV2`)
+    #[arg(long, value_name = "RANGE", group = "git_changes")]
+    diff: Option<String>,
+
+    /// Add each changed file's unified diff next to its content, or instead
+    /// of it
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        default_missing_value = "beside",
+        requires = "git_changes",
+        conflicts_with_all = ["split", "archive"]
+    )]
+    show_diff: Option<DiffDisplay>,
+
+    /// Include earlier rosetree reports found in the scanned tree
+    #[arg(long)]
+    include_reports: bool,
+
+    /// Skip files larger than this many bytes, listing them as skipped
+    #[arg(long, value_name = "BYTES")]
+    max_file_size: Option<u64>,
+
+    /// Write a JSON summary of the processed and skipped files to this path
+    #[arg(long, value_name = "PATH")]
+    summary: Option<PathBuf>,
+
+    /// Exit with an error when any file or directory could not be read,
+    /// even if the report was written
+    #[arg(long)]
+    strict: bool,
+
+    /// Neither use nor update the scan cache kept between runs
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Print per-file and total token estimates
+    #[arg(long)]
+    tokens: bool,
+
+    /// Tokenizer used for token estimates
+    #[arg(long, value_enum, default_value_t)]
+    tokenizer: TokenizerKind,
+
+    /// Keep the report within this many estimated tokens
+    #[arg(long, value_name = "N", conflicts_with = "split")]
+    max_tokens: Option<usize>,
+
+    /// How files give way when the report would exceed --max-tokens
+    #[arg(long, value_enum, default_value_t)]
+    budget_policy: BudgetPolicy,
+
+    /// Split the report into parts with at most N bytes of file contents each
+    #[arg(long, value_name = "N", group = "split")]
+    split_bytes: Option<usize>,
+
+    /// Split the report into parts with at most N lines of file contents each
+    #[arg(long, value_name = "N", group = "split")]
+    split_lines: Option<usize>,
+
+    /// Split the report into parts with at most N estimated tokens of file
+    /// contents each
+    #[arg(long, value_name = "N", group = "split")]
+    split_tokens: Option<usize>,
+
+    /// Write the file tree only into the first part of a split report
+    #[arg(long)]
+    tree_once: bool,
+
+    /// Wrap XML content in CDATA sections instead of escaping it
+    #[arg(long)]
+    xml_cdata: bool,
+
+    /// Comma-separated attributes added to each XML document element
+    #[arg(long, value_enum, value_name = "ATTRS", value_delimiter = ',')]
+    xml_attrs: Vec<XmlAttribute>,
+
+    /// Format-specific settings resolved from the options above and the config
+    #[arg(skip)]
+    render: RenderOptions,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Recreate the files of a Markdown report in a directory
+    Unpack(UnpackArgs),
+    /// Apply unified diffs and whole-file blocks from a model response
+    Apply(ApplyArgs),
+    /// Manage the scan cache kept between runs
+    #[command(subcommand)]
+    Cache(CacheCommand),
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Remove the cached scans of every project
+    Clear,
+}
+
+impl Cli {
+    fn gitignore_choice(&self) -> Option<bool> {
+        if self.gitignore {
+            Some(true)
+        } else if self.no_gitignore {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    fn split_limit(&self) -> Option<SplitLimit> {
+        if let Some(max) = self.split_bytes {
+            Some(SplitLimit::Bytes(max))
+        } else if let Some(max) = self.split_lines {
+            Some(SplitLimit::Lines(max))
+        } else {
+            self.split_tokens
+                .map(|max| SplitLimit::Tokens(max, self.tokenizer.build()))
+        }
+    }
+
+    /// With `--strict`, files that could not be read fail the run even when
+    /// the report was written.
+    fn outcome(&self, skipped: &[SkippedFile], failed: &[SkippedFile]) -> Outcome {
+        let unreadable =
+            skipped.iter().filter(|s| s.error.is_read_failure()).count() + failed.len();
+        if self.strict && unreadable > 0 {
+            eprintln!("Error: {unreadable} paths could not be read (--strict)");
+            Outcome::ReadFailed
+        } else {
+            Outcome::Done
+        }
+    }
+
+    /// Write the `--summary` file, if one was asked for.
+    fn write_summary(
+        &self,
+        files_processed: usize,
+        skipped: &[SkippedFile],
+        failed: &[SkippedFile],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(path) = &self.summary else {
+            return Ok(());
+        };
+        let summary = serde_json::json!({
+            "files_processed": files_processed,
+            "files_failed": failed.len(),
+            "skipped": skipped,
+            "failed": failed,
+        });
+        fs::write(path, format!("{summary:#}\n"))
+            .map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+        Ok(())
+    }
+
+    fn marked_files(&self) -> MarkedFiles {
+        MarkedFiles {
+            generated: self.include_generated,
+            vendored: self.include_vendored,
+        }
+    }
+
+    /// The scan described by the command line, writing to `output`.
+    fn scanner(
+        &self,
+        root: &Path,
+        gitignore: bool,
+        output: &Path,
+        cache: Option<&Arc<ScanCache>>,
+    ) -> Scanner {
+        let mut scanner = Scanner::new(root)
+            .gitignore(gitignore)
+            .include_marked(self.marked_files())
+            .include_reports(self.include_reports);
+        for glob in &self.include {
+            scanner = scanner.include(glob);
+        }
+        for glob in &self.exclude {
+            scanner = scanner.exclude(glob);
+        }
+        if output.as_os_str() != "-" {
+            scanner = scanner.output(output);
+        }
+        if let Some(rev) = &self.rev {
+            scanner = scanner.rev(rev);
+        }
+        if let Some(bytes) = self.max_file_size {
+            scanner = scanner.max_file_size(bytes);
+        }
+        if let Some(changes) = self.change_set() {
+            scanner = scanner.changes(changes, self.show_diff.is_some());
+        }
+        if let Some(cache) = cache {
+            scanner = scanner.cache(Arc::clone(cache));
+        }
+        scanner
+    }
+
+    fn change_set(&self) -> Option<ChangeSet> {
+        if self.changed {
+            Some(ChangeSet::Changed)
+        } else if self.staged {
+            Some(ChangeSet::Staged)
+        } else if let Some(rev) = &self.since {
+            Some(ChangeSet::Since(rev.clone()))
+        } else {
+            self.diff.clone().map(ChangeSet::Range)
+        }
+    }
+
+    /// Whether the gitignore or extension prompt may still be shown once the
+    /// project config is applied.
+    fn prompts_pending(&self, config: &Config) -> bool {
+        let gitignore =
+            self.rev.is_none() && self.gitignore_choice().is_none() && config.gitignore.is_none();
+        let extensions = !self.pick
+            && !self.all
+            && self.ext.is_empty()
+            && !config.all
+            && config.extensions.is_empty();
+        gitignore || extensions
+    }
+
+    /// Fill in values not given on the command line from a saved selection.
+    /// Returns whether its extensions are used.
+    fn apply_selection(&mut self, selection: &Selection) -> bool {
+        match (self.gitignore_choice(), selection.gitignore) {
+            (None, Some(true)) => self.gitignore = true,
+            (None, Some(false)) => self.no_gitignore = true,
+            _ => {}
+        }
+        if self.include.is_empty() {
+            self.include.clone_from(&selection.include);
+        }
+        if self.exclude.is_empty() {
+            self.exclude.clone_from(&selection.exclude);
+        }
+        if self.all || !self.ext.is_empty() {
+            return false;
+        }
+        self.all = selection.all;
+        self.ext.clone_from(&selection.extensions);
+        true
+    }
+
+    /// Fill in values not given on the command line from the project config.
+    fn apply_config(&mut self, config: Config) {
+        if self.gitignore_choice().is_none() {
+            match config.gitignore {
+                Some(true) => self.gitignore = true,
+                Some(false) => self.no_gitignore = true,
+                None => {}
+            }
+        }
+        if !self.all && self.ext.is_empty() {
+            self.all = config.all;
+            self.ext = config.extensions;
+        }
+        if self.include.is_empty() {
+            self.include = config.include;
+        }
+        if self.exclude.is_empty() {
+            self.exclude = config.exclude;
+        }
+        if self.output.is_none() {
+            self.output = config.output;
+        }
+        if self.format.is_none() {
+            self.format = config.format;
+        }
+        self.render.xml = config.xml;
+        if self.xml_cdata {
+            self.render.xml.cdata = true;
+        }
+        if !self.xml_attrs.is_empty() {
+            self.render.xml.attributes.clone_from(&self.xml_attrs);
+        }
+        self.render.diff_only = self.show_diff == Some(DiffDisplay::Instead);
+    }
+}
+
+/// How a run ended when it did not fail with an error.
+enum Outcome {
+    Done,
+    /// No files were found or selected, so nothing was written.
+    NothingMatched,
+    /// Every selected file failed to read or, with `--strict`, any of them.
+    ReadFailed,
+}
+
+/// Exit codes besides 0 for success, 1 for errors and 2 for invalid
+/// arguments, which clap uses.
+const EXIT_NOTHING_MATCHED: u8 = 3;
+const EXIT_READ_FAILED: u8 = 4;
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(Outcome::Done) => ExitCode::SUCCESS,
+        Ok(Outcome::NothingMatched) => ExitCode::from(EXIT_NOTHING_MATCHED),
+        Ok(Outcome::ReadFailed) => ExitCode::from(EXIT_READ_FAILED),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
 #[allow(clippy::too_many_lines)]
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn run() -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut cli = Cli::parse();
+    match &cli.command {
+        Some(Command::Unpack(args)) => return unpack::run(args).map(|()| Outcome::Done),
+        Some(Command::Apply(args)) => return apply::run(args).map(|()| Outcome::Done),
+        Some(Command::Cache(CacheCommand::Clear)) => {
+            match ScanCache::clear_all()? {
+                Some(dir) => eprintln!("Removed the scan cache in {}", dir.display()),
+                None => eprintln!("No scan cache to remove"),
+            }
+            return Ok(Outcome::Done);
+        }
+        None => {}
+    }
+    let interactive = io::stdin().is_terminal();
+    if cli.pick && !(interactive && io::stderr().is_terminal()) {
+        return Err("--pick needs an interactive terminal".into());
+    }
     let mut timings = Timings::new();
 
-    println!("Scanning current directory and subdirectories...");
+    let current_dir = match &cli.root {
+        Some(root) => root
+            .canonicalize()
+            .map_err(|e| format!("Unable to access {}: {e}", root.display()))?,
+        None => {
+            std::env::current_dir().map_err(|e| format!("Unable to get current directory: {e}"))?
+        }
+    };
+    if !current_dir.is_dir() {
+        return Err(format!("Not a directory: {}", current_dir.display()).into());
+    }
+    let config = Config::load(&current_dir)?;
+    let saved = Selection::load(&current_dir);
+    let reused = if cli.last {
+        Some(saved.ok_or_else(|| format!("No saved selection for {}", current_dir.display()))?)
+    } else if interactive
+        && cli.prompts_pending(&config)
+        && let Some(saved) = saved
+    {
+        eprintln!("\nLast selection: {}", saved.describe());
+        eprintln!("Reuse last selection? (Y/n):");
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        matches!(input.trim().to_lowercase().as_str(), "" | "y" | "yes").then_some(saved)
+    } else {
+        None
+    };
+    // Extensions of a reused selection, checked against the files found
+    let mut last_extensions = Vec::new();
+    if let Some(selection) = reused
+        && cli.apply_selection(&selection)
+    {
+        last_extensions = selection.extensions;
+    }
+    // Globs from the config apply to every run, so only these are saved
+    let (include, exclude) = (cli.include.clone(), cli.exclude.clone());
+    cli.apply_config(config);
+    let mut prompted = false;
+
+    match &cli.rev {
+        Some(rev) => eprintln!("Scanning {} at {rev}...", current_dir.display()),
+        None => eprintln!("Scanning {} and subdirectories...", current_dir.display()),
+    }
 
-    let current_dir =
-        std::env::current_dir().map_err(|e| format!("Unable to get current directory: {e}"))?;
+    let format = cli.format.unwrap_or_default();
+    let filename = expand_output_template(
+        &cli.output.clone().unwrap_or_else(|| {
+            let extension = cli
+                .archive
+                .map_or(format.extension(), ArchiveFormat::extension);
+            format!("{DEFAULT_OUTPUT_STEM}.{extension}")
+        }),
+        &current_dir,
+    );
 
+    // The walk lists the .gitignore files it meets, so it starts with the
+    // likely answer and is only redone when .gitignore rules turn out not to
+    // apply. No file is opened until the answer is known. Tracked files
+    // already leave out whatever .gitignore excludes.
     let stage_start_time = Instant::now();
-    let gitignore_files = find_gitignore_files(&current_dir);
-    timings.find_gitignore = stage_start_time.elapsed().as_micros();
+    // Files at a revision are read from git, where the cache does not apply
+    let cache =
+        (!cli.no_cache && cli.rev.is_none()).then(|| Arc::new(ScanCache::load(&current_dir)));
+    let gitignore = cli.rev.is_none() && cli.gitignore_choice() != Some(false);
+    let mut scanner = cli.scanner(&current_dir, gitignore, &filename, cache.as_ref());
+    let mut walk = scanner.walk()?;
+    timings.collect_files = stage_start_time.elapsed().as_micros();
+    let gitignore_files = std::mem::take(&mut walk.gitignore_files);
 
     let use_gitignore = if gitignore_files.is_empty() {
         false
-    } else {
-        println!("\nFound the following .gitignore files:");
+    } else if let Some(choice) = cli.gitignore_choice() {
+        choice
+    } else if interactive {
+        eprintln!("\nFound the following .gitignore files:");
         for info in &gitignore_files {
-            println!("  - {}", info.relative_path);
+            eprintln!("  - {}", info.relative_path);
         }
 
-        println!("\nApply .gitignore rules? (y/n):");
+        eprintln!("\nApply .gitignore rules? (y/n):");
         let mut input = String::new();
         io::stdin().read_line(&mut input)?;
+        prompted = true;
         input.trim().to_lowercase() == "y"
-    };
-
-    let stage_start_time = Instant::now();
-    let files = if use_gitignore {
-        collect_files_with_gitignore(&current_dir)
     } else {
-        collect_files_without_gitignore(&current_dir)
+        // Non-interactive runs default to respecting the repository's ignore rules
+        true
     };
-    timings.collect_files = stage_start_time.elapsed().as_micros();
+    let stage_start_time = Instant::now();
+    if use_gitignore != gitignore {
+        scanner = cli.scanner(&current_dir, use_gitignore, &filename, cache.as_ref());
+        walk = scanner.walk()?;
+    }
+    let scan = scanner.classify(walk)?;
+    timings.collect_files += stage_start_time.elapsed().as_micros();
+    save_cache(cache.as_deref());
+    let files = scan.files;
+    let source = scan.source.as_ref();
+    let skipped = scan.skipped;
 
     if files.is_empty() {
-        println!("No UTF-8 readable files found.");
-        timings.total = timings.find_gitignore + timings.collect_files;
+        eprintln!("No UTF-8 readable files found.");
+        timings.total = timings.collect_files;
         print_timings(&timings);
-        return Ok(());
+        return Ok(Outcome::NothingMatched);
     }
 
-    let extensions_set: HashSet<String> = files.iter().map(|f| f.extension.clone()).collect();
-    let mut extensions_vec: Vec<String> = extensions_set.into_iter().collect();
-    extensions_vec.sort();
+    let extensions_vec = available_extensions(&files);
 
-    println!("\nFound the following UTF-8 file types:");
-    for (i, ext) in extensions_vec.iter().enumerate() {
-        println!(
-            "{}. {}",
-            i + 1,
-            if ext.is_empty() { "no extension" } else { ext }
+    let missing: Vec<&str> = last_extensions
+        .iter()
+        .filter(|e| !extensions_vec.contains(e))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        eprintln!(
+            "Warning: No files found with extensions from the last selection: {}",
+            missing.join(", ")
         );
     }
 
-    println!("\nEnter file type numbers to extract (space-separated, 'a' for all types):");
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .map_err(|e| format!("Failed to read input: {e}"))?;
-
-    let selected_extensions: HashSet<String> = if input.trim().to_lowercase() == "a" {
+    let select_all = cli.all || (cli.pick && cli.ext.is_empty());
+    let mut chose_all = false;
+    let selected_extensions: HashSet<String> = if select_all {
         extensions_vec.iter().cloned().collect()
-    } else {
-        input
-            .split_whitespace()
-            .filter_map(|s| s.parse::<usize>().ok())
-            .filter_map(|i| extensions_vec.get(i.saturating_sub(1)).cloned())
+    } else if !cli.ext.is_empty() {
+        cli.ext
+            .iter()
+            .map(|e| e.trim().trim_start_matches('.').to_string())
+            .filter(|e| extensions_vec.contains(e))
             .collect()
+    } else if interactive {
+        prompted = true;
+        let chosen = prompt_extensions(&extensions_vec)?;
+        chose_all = chosen.is_none();
+        chosen.unwrap_or_else(|| extensions_vec.iter().cloned().collect())
+    } else {
+        return Err("No file types given; pass --ext or --all when stdin is not a terminal".into());
     };
 
     if selected_extensions.is_empty() {
-        println!("No file types selected.");
-        timings.total = timings.find_gitignore + timings.collect_files;
+        eprintln!("No file types selected.");
+        timings.total = timings.collect_files;
         print_timings(&timings);
-        return Ok(());
+        return Ok(Outcome::NothingMatched);
     }
 
-    let selected_files: Vec<FileInfo> = files
-        .into_par_iter()
-        .filter(|f| selected_extensions.contains(&f.extension))
-        .collect();
+    if prompted {
+        let mut extensions: Vec<String> = selected_extensions.iter().cloned().collect();
+        extensions.sort();
+        let selection = Selection {
+            root: current_dir.clone(),
+            gitignore: (!gitignore_files.is_empty()).then_some(use_gitignore),
+            all: cli.all || chose_all,
+            extensions: if cli.all || chose_all {
+                Vec::new()
+            } else {
+                extensions
+            },
+            include,
+            exclude,
+        };
+        if let Err(e) = selection.save() {
+            eprintln!("Warning: Failed to save the selection: {e}");
+        }
+    }
+
+    // Skipped files have extensions no scanned file has, which only `--all` covers
+    let selector = if select_all {
+        Selector::all()
+    } else {
+        Selector::extensions(&selected_extensions)
+    };
+    let selected_files = selector.select(files);
+    let skipped = selector.select_skipped(skipped);
 
     if selected_files.is_empty() {
-        println!("No matching files found.");
-        timings.total = timings.find_gitignore + timings.collect_files;
+        eprintln!("No matching files found.");
+        timings.total = timings.collect_files;
         print_timings(&timings);
-        return Ok(());
+        return Ok(Outcome::NothingMatched);
     }
 
-    // Pre-sort files by path to avoid later sorting
+    // Selected files come sorted by path
     let mut sorted_files = selected_files;
-    sorted_files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+
+    if cli.pick {
+        let tokenizer = cli.tokenizer.build();
+        let picked = picker::pick(source, &sorted_files, tokenizer.as_ref(), cache.as_deref())?;
+        save_cache(cache.as_deref());
+        let Some(picked) = picked else {
+            eprintln!("Selection cancelled.");
+            return Ok(Outcome::Done);
+        };
+        sorted_files = picked;
+    }
 
     if sorted_files.is_empty() {
-        println!("No matching files found.");
-        timings.total = timings.find_gitignore + timings.collect_files;
+        eprintln!("No matching files found.");
+        timings.total = timings.collect_files;
         print_timings(&timings);
-        return Ok(());
+        return Ok(Outcome::NothingMatched);
     }
 
     // Generate tree structure (for display only)
     let stage_start_time = Instant::now();
-    let tree_structure = generate_tree_structure_from_files(&sorted_files);
+    let tree_structure = generate_tree_structure(&sorted_files);
     timings.generate_tree = stage_start_time.elapsed().as_micros();
 
-    // Create output file
-    let current_time = Local::now();
-    let timestamp_str = current_time.format("%Y%m%d_%H%M%S").to_string();
-    let filename = format!("rosetree_{timestamp_str}.md");
+    if let Some(archive_format) = cli.archive {
+        if filename.as_os_str() == "-" {
+            return Err("An archive cannot be written to stdout".into());
+        }
+        eprintln!("\nPacking files...");
+        let stage_start_time = Instant::now();
+        let summary = write_archive(
+            source,
+            &sorted_files,
+            &tree_structure,
+            &filename,
+            archive_format,
+            skipped,
+        )
+        .map_err(|e| format!("Failed to write {}: {e}", filename.display()))?;
+        timings.write_file = stage_start_time.elapsed().as_micros();
+        cli.write_summary(summary.files_processed, &summary.skipped, &summary.failed)?;
+        print_skipped(&summary.skipped, &summary.failed, "the MANIFEST");
+        if summary.files_processed == 0 && !summary.failed.is_empty() {
+            eprintln!("Error: All selected files failed to read.");
+            return Ok(Outcome::ReadFailed);
+        }
+        eprintln!(
+            "Successfully processed {} files ({} failed)",
+            summary.files_processed,
+            summary.failed.len()
+        );
+        eprintln!("\nFiles successfully packed to: {}", filename.display());
+        timings.total = timings.collect_files + timings.generate_tree + timings.write_file;
+        print_timings(&timings);
+        return Ok(cli.outcome(&summary.skipped, &summary.failed));
+    }
+
+    if let Some(limit) = cli.split_limit() {
+        if filename.as_os_str() == "-" {
+            return Err("A split report cannot be written to stdout".into());
+        }
+        if format != OutputFormat::Markdown {
+            return Err("Only Markdown reports can be split into parts".into());
+        }
+        eprintln!("\nWriting file contents...");
+        let stage_start_time = Instant::now();
+        let summary = write_files_chunked(
+            source,
+            &sorted_files,
+            &tree_structure,
+            &filename,
+            &limit,
+            cli.tree_once,
+            skipped,
+        )?;
+        timings.read_contents = stage_start_time.elapsed().as_micros();
+        cli.write_summary(summary.files_processed, &summary.skipped, &summary.failed)?;
+        print_skipped(&summary.skipped, &summary.failed, "the last part");
+        if summary.files_processed == 0 && !summary.failed.is_empty() {
+            eprintln!("Error: All selected files failed to read.");
+            return Ok(Outcome::ReadFailed);
+        }
+        eprintln!(
+            "Successfully processed {} files ({} failed)",
+            summary.files_processed,
+            summary.failed.len()
+        );
+        eprintln!("\nFile contents successfully extracted to:");
+        for part in &summary.parts {
+            eprintln!("  - {}", part.display());
+        }
+        timings.total = timings.collect_files + timings.read_contents + timings.generate_tree;
+        print_timings(&timings);
+        return Ok(cli.outcome(&summary.skipped, &summary.failed));
+    }
 
     // Use streaming processing: read and write simultaneously
     let stage_start_time = Instant::now();
-    write_files_streaming(&sorted_files, &tree_structure, &filename, &mut timings)?;
+    let to_stdout = filename.as_os_str() == "-";
+    let writer: Box<dyn Write> = if to_stdout {
+        Box::new(BufWriter::new(io::stdout().lock()))
+    } else {
+        let output_file = fs::File::create(&filename)
+            .map_err(|e| format!("Failed to create output file: {e}"))?;
+        Box::new(BufWriter::new(output_file))
+    };
+    // Writing overlaps reading, so it is timed with the contents below
     timings.write_file = stage_start_time.elapsed().as_micros();
+    let token_options = (cli.tokens || cli.max_tokens.is_some()).then(|| TokenOptions {
+        tokenizer: cli.tokenizer.build(),
+        max_tokens: cli.max_tokens,
+        policy: cli.budget_policy,
+        cache: cache.clone(),
+    });
+    let plan = token_options.as_ref().map(|options| {
+        eprintln!("\nCounting tokens ({})...", options.tokenizer.name());
+        let stage_start_time = Instant::now();
+        let plan = plan_tokens(
+            source,
+            &sorted_files,
+            &tree_structure,
+            format,
+            &cli.render,
+            options,
+        );
+        timings.count_tokens = stage_start_time.elapsed().as_micros();
+        save_cache(options.cache.as_deref());
+        plan
+    });
+    let budget = token_options
+        .as_ref()
+        .zip(plan.as_ref())
+        .map(|(options, plan)| TokenBudget { options, plan });
+
+    eprintln!("\nWriting file contents...");
+    let contents_start_time = Instant::now();
+    let summary = write_report(
+        writer,
+        source,
+        &sorted_files,
+        &tree_structure,
+        format,
+        &cli.render,
+        budget,
+        skipped,
+    )?;
+    timings.read_contents = contents_start_time.elapsed().as_micros();
+    if let Some(TokenBudget { options, plan }) = budget {
+        print_token_summary(&sorted_files, plan, options);
+    }
+    cli.write_summary(summary.files_processed, &summary.skipped, &summary.failed)?;
+    print_skipped(&summary.skipped, &summary.failed, "the report");
+    if summary.files_processed == 0 && !summary.failed.is_empty() {
+        eprintln!("Error: All selected files failed to read.");
+        return Ok(Outcome::ReadFailed);
+    }
+    eprintln!(
+        "Successfully processed {} files ({} failed)",
+        summary.files_processed,
+        summary.failed.len()
+    );
+    if !to_stdout {
+        eprintln!(
+            "\nFile contents successfully extracted to: {}",
+            filename.display()
+        );
+    }
 
-    println!("\nFile contents successfully extracted to: {filename}");
-
-    timings.total = timings.find_gitignore
-        + timings.collect_files
+    timings.total = timings.collect_files
         + timings.read_contents
+        + timings.count_tokens
         + timings.generate_tree
         + timings.generate_output_string
         + timings.write_file;
 
     print_timings(&timings);
 
-    Ok(())
+    Ok(cli.outcome(&summary.skipped, &summary.failed))
 }
 
-fn print_timings(timings: &Timings) {
-    println!("\nProgram Operation Execution Times (µs):");
-    println!("-------------------------------------------");
-    println!("Find .gitignore files:     {:>10}", timings.find_gitignore);
-    println!("Collect files:             {:>10}", timings.collect_files);
-    println!("Read selected contents:    {:>10}", timings.read_contents);
-    println!("Generate tree structure:   {:>10}", timings.generate_tree);
-    println!(
-        "Generate output string:    {:>10}",
-        timings.generate_output_string
-    );
-    println!("Write to file:             {:>10}", timings.write_file);
-    println!("-------------------------------------------");
-    println!("Total processing time:     {:>10} µs", timings.total);
-    println!(
-        "                           {:>10} ms (approx total)",
-        timings.total / 1000
-    );
-    println!("-------------------------------------------");
-}
-
-fn find_gitignore_files(base_dir: &Path) -> Vec<GitIgnoreInfo> {
-    let mut gitignore_files = Vec::new();
-    let walker = WalkBuilder::new(base_dir)
-        .standard_filters(false)
-        .hidden(false)
-        .parents(false)
-        .ignore(false)
-        .git_global(false)
-        .git_exclude(false)
-        .git_ignore(false)
-        .filter_entry(|e| e.file_name() != std::ffi::OsStr::new(".git"))
-        .build();
-
-    for result in walker {
-        match result {
-            Ok(entry) => {
-                if entry.file_type().is_some_and(|ft| ft.is_file())
-                    && entry.file_name() == std::ffi::OsStr::new(".gitignore")
-                    && !entry.path_is_symlink()
-                {
-                    let path = entry.path();
-                    let relative_path = path
-                        .strip_prefix(base_dir)
-                        .unwrap_or(path)
-                        .to_string_lossy()
-                        .replace('\\', "/");
-                    gitignore_files.push(GitIgnoreInfo { relative_path });
-                }
-            }
-            Err(err) => {
-                eprintln!("Warning: Error finding .gitignore files: {err}");
-            }
-        }
-    }
-    gitignore_files
-}
-
-fn collect_files_with_gitignore(base_dir: &Path) -> Vec<FileInfo> {
-    let mut files = Vec::new();
-    let walker = WalkBuilder::new(base_dir)
-        .git_ignore(true)
-        .git_global(true)
-        .git_exclude(true)
-        .parents(true)
-        .ignore(true)
-        .hidden(false)
-        .follow_links(false)
-        .build();
-
-    for result in walker {
-        match result {
-            Ok(entry) => {
-                let path = entry.path();
-                if path.is_dir() || path.components().any(|c| c.as_os_str() == ".git") {
-                    continue;
-                }
-                if !is_utf8_file(path) {
-                    continue;
-                }
-
-                let relative_path = path
-                    .strip_prefix(base_dir)
-                    .unwrap_or(path)
-                    .to_string_lossy()
-                    .replace('\\', "/");
-                let extension = path
-                    .extension()
-                    .and_then(|e| e.to_str())
-                    .unwrap_or("")
-                    .to_string();
-                files.push(FileInfo {
-                    path: path.to_path_buf(),
-                    relative_path,
-                    extension,
-                });
-            }
-            Err(err) => {
-                eprintln!("Warning: Error walking directory (with gitignore): {err}");
-            }
-        }
+/// Read failures are listed one by one as they were before reports listed
+/// them; paths the scan left out are only counted.
+fn print_skipped(skipped: &[SkippedFile], failed: &[SkippedFile], listed_in: &str) {
+    for failure in failed {
+        eprintln!(
+            "Warning: Failed to read {}: {}",
+            failure.relative_path, failure.error
+        );
     }
-    files
-}
-
-fn collect_files_without_gitignore(base_dir: &Path) -> Vec<FileInfo> {
-    let files_map = Arc::new(DashMap::new());
-    collect_files_recursive(base_dir, base_dir, &files_map);
-    files_map
-        .iter()
-        .map(|entry| entry.value().clone())
-        .collect()
-}
-
-fn collect_files_recursive(
-    dir: &Path,
-    base_dir: &Path,
-    files_map: &Arc<DashMap<PathBuf, FileInfo>>,
-) {
-    let Ok(entries_result) = fs::read_dir(dir) else {
-        eprintln!("Warning: Failed to read directory: {}", dir.display());
-        return;
-    };
-
-    let entries: Vec<PathBuf> = entries_result
-        .filter_map(Result::ok)
-        .map(|e| e.path())
-        .collect();
-
-    entries.into_par_iter().for_each(|path| {
-        if path.is_dir() {
-            if path.file_name().and_then(|n| n.to_str()) == Some(".git") {
-                return;
-            }
-            collect_files_recursive(&path, base_dir, files_map);
-        } else if path.is_file() && is_utf8_file(&path) {
-            let relative_path = path
-                .strip_prefix(base_dir)
-                .unwrap_or(&path)
-                .to_string_lossy()
-                .replace('\\', "/");
-            let extension = path
-                .extension()
-                .and_then(|e| e.to_str())
-                .unwrap_or("")
-                .to_string();
-            let file_info = FileInfo {
-                path: path.clone(),
-                relative_path,
-                extension,
-            };
-            files_map.insert(path.clone(), file_info);
-        }
-    });
-}
-
-fn is_utf8_file(path: &Path) -> bool {
-    // First check by file extension for known text file types
-    if is_known_text_extension(path) {
-        return true;
-    }
-    
-    // For unknown extensions, perform content inspection
-    match fs::File::open(path) {
-        Ok(mut file) => {
-            // Read up to 8KB for better detection accuracy
-            let mut buffer = [0u8; 8192]; 
-            match file.read(&mut buffer) {
-                Ok(0) => true, // Empty files are considered text files
-                Ok(bytes_read) => {
-                    let sample = &buffer[..bytes_read];
-                    
-                    // Multiple checks for better accuracy:
-                    // 1. Use content_inspector as primary check
-                    if inspect(sample).is_text() {
-                        return true;
-                    }
-                    
-                    // 2. Check if it's valid UTF-8 and contains mostly printable chars
-                    if let Ok(text) = std::str::from_utf8(sample) {
-                        return is_mostly_printable_text(text);
-                    }
-                    
-                    // 3. Final fallback: very small files with some text content
-                    if bytes_read < 256 && has_some_text_chars(sample) {
-                        return true;
-                    }
-                    
-                    false
-                }
-                Err(_) => false,
-            }
-        }
-        Err(_) => false,
+    if !skipped.is_empty() {
+        eprintln!(
+            "Skipped {} paths that are not text or could not be read, listed in {listed_in}",
+            skipped.len()
+        );
     }
 }
 
-fn is_known_text_extension(path: &Path) -> bool {
-    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-        let ext_lower = ext.to_lowercase();
-        matches!(ext_lower.as_str(),
-            // Programming languages
-            "rs" | "py" | "js" | "ts" | "jsx" | "tsx" | "java" | "c" | "cpp" | "cc" | "cxx" | 
-            "h" | "hpp" | "cs" | "php" | "rb" | "go" | "kt" | "swift" | "scala" | "clj" | 
-            "hs" | "elm" | "erl" | "ex" | "exs" | "ml" | "fs" | "vb" | "pas" | "pl" | "pm" |
-            "r" | "jl" | "m" | "mm" | "f" | "f90" | "f95" | "ada" | "d" | "nim" | "zig" |
-            
-            // Web and markup
-            "html" | "htm" | "xml" | "xhtml" | "svg" | "css" | "scss" | "sass" | "less" |
-            "vue" | "svelte" | "astro" | "ejs" | "handlebars" | "hbs" |
-            
-            // Data formats
-            "json" | "yaml" | "yml" | "toml" | "ini" | "cfg" | "conf" | "properties" |
-            "csv" | "tsv" | "txt" | "log" |
-            
-            // Documentation
-            "md" | "markdown" | "rst" | "tex" | "latex" | "org" | "adoc" | "asciidoc" |
-            
-            // Scripts and config
-            "sh" | "bash" | "zsh" | "fish" | "ps1" | "cmd" | "bat" | "dockerfile" |
-            "makefile" | "mk" | "cmake" | "ninja" | "gradle" | "ant" |
-            
-            // Other common text files
-            "gitignore" | "gitattributes" | "editorconfig" | "prettierrc" | "eslintrc" |
-            "tsconfig" | "package" | "cargo" | "gemfile" | "pipfile" | "requirements" |
-            "license" | "readme" | "changelog" | "authors" | "contributors" | "todo"
-        )
-    } else {
-        // Files without extension that are commonly text files
-        if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
-            let filename_lower = filename.to_lowercase();
-            matches!(filename_lower.as_str(),
-                "makefile" | "dockerfile" | "cmakelists.txt" | "readme" | "license" | 
-                "changelog" | "authors" | "contributors" | "todo" | "news" | "install" |
-                "copying" | "notice" | "manifest" | "version" | "gemfile" | "rakefile" |
-                "pipfile" | "procfile" | "vagrantfile" | "gruntfile" | "gulpfile" |
-                ".gitignore" | ".gitattributes" | ".editorconfig" | ".prettierrc" |
-                ".eslintrc" | ".babelrc" | ".npmrc" | ".yarnrc"
-            )
-        } else {
-            false
-        }
+/// Ask which of `extensions_vec` to extract; `None` when the user picks all
+/// of them with `a`.
+fn prompt_extensions(extensions_vec: &[String]) -> io::Result<Option<HashSet<String>>> {
+    eprintln!("\nFound the following UTF-8 file types:");
+    for (i, ext) in extensions_vec.iter().enumerate() {
+        eprintln!(
+            "{}. {}",
+            i + 1,
+            if ext.is_empty() { "no extension" } else { ext }
+        );
     }
-}
-
-fn is_mostly_printable_text(text: &str) -> bool {
-    if text.is_empty() {
-        return true;
-    }
-    
-    let total_chars = text.chars().count();
-    let printable_chars = text.chars()
-        .filter(|&c| c.is_ascii_graphic() || c.is_ascii_whitespace() || !c.is_ascii())
-        .count();
-    
-    // At least 85% of characters should be printable
-    (printable_chars as f64 / total_chars as f64) >= 0.85
-}
-
-fn has_some_text_chars(data: &[u8]) -> bool {
-    let text_chars = data.iter()
-        .filter(|&&b| b.is_ascii_alphabetic() || b.is_ascii_digit() || b.is_ascii_whitespace())
-        .count();
-    
-    // For very small files, if at least 50% are text characters, consider it text
-    (text_chars as f64 / data.len() as f64) >= 0.5
-}
 
+    eprintln!("\nEnter file type numbers to extract (space-separated, 'a' for all types):");
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
 
-fn write_files_streaming(
-    files: &[FileInfo],
-    tree_structure: &str,
-    filename: &str,
-    timings: &mut Timings,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("\nWriting file contents...");
-    
-    let output_file = fs::File::create(filename)
-        .map_err(|e| format!("Failed to create output file: {e}"))?;
-    let mut writer = BufWriter::new(output_file);
-    
-    // Write Markdown formatted project analysis report
-    write!(writer, "# Project Analysis Report\n\n")?;
-    write!(writer, "## File Structure\n\n```\n{tree_structure}```\n\n")?;
-    write!(writer, "## File Contents\n\n")?;
-    
-    let stage_start_time = Instant::now();
-    let mut files_processed = 0;
-    let mut files_failed = 0;
-    
-    // Stream process each file
-    for file_info in files {
-        match read_and_write_file(&mut writer, file_info) {
-            Ok(()) => {
-                files_processed += 1;
-            }
-            Err(e) => {
-                eprintln!("Warning: Failed to read {}: {}", file_info.relative_path, e);
-                files_failed += 1;
-            }
-        }
+    if input.trim().to_lowercase() == "a" {
+        return Ok(None);
     }
-    
-    writer.flush()?;
-    timings.read_contents = stage_start_time.elapsed().as_micros();
-    timings.generate_output_string = 0; // Already included in streaming process
-    
-    if files_processed == 0 && files_failed > 0 {
-        return Err("All selected files failed to read.".into());
-    }
-    
-    println!("Successfully processed {files_processed} files ({files_failed} failed)");
-    Ok(())
-}
-
-fn read_and_write_file(
-    writer: &mut BufWriter<fs::File>,
-    file_info: &FileInfo,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Write Markdown file header
-    write!(writer, "### `{}`\n\n", file_info.relative_path)?;
-    
-    // Determine syntax highlighting type based on extension
-    let language = get_language_from_extension(&file_info.extension);
-    writeln!(writer, "```{language}")?;
-    
-    // Stream read and write file content
-    let file = fs::File::open(&file_info.path)?;
-    let mut reader = BufReader::new(file);
-    let mut line = String::new();
-    
-    while reader.read_line(&mut line)? > 0 {
-        writer.write_all(line.as_bytes())?;
-        line.clear();
-    }
-    
-    write!(writer, "```\n\n")?;
-    Ok(())
+    Ok(Some(
+        input
+            .split_whitespace()
+            .filter_map(|s| s.parse::<usize>().ok())
+            .filter_map(|i| extensions_vec.get(i.saturating_sub(1)).cloned())
+            .collect(),
+    ))
 }
 
-fn get_language_from_extension(extension: &str) -> &'static str {
-    match extension {
-        "rs" => "rust",
-        "js" => "javascript",
-        "ts" => "typescript",
-        "py" => "python",
-        "go" => "go",
-        "java" => "java",
-        "c" | "h" | "hpp" => "c",
-        "cpp" | "cc" | "cxx" => "cpp",
-        "html" => "html",
-        "css" => "css",
-        "json" => "json",
-        "xml" => "xml",
-        "yaml" | "yml" => "yaml",
-        "toml" => "toml",
-        "md" => "markdown",
-        "sh" => "bash",
-        "sql" => "sql",
-        "dockerfile" => "dockerfile",
-        "makefile" => "makefile",
-        _ => "", // No syntax highlighting
+fn save_cache(cache: Option<&ScanCache>) {
+    if let Some(cache) = cache
+        && let Err(e) = cache.save()
+    {
+        eprintln!("Warning: Failed to save the scan cache: {e}");
     }
 }
 
-fn generate_tree_structure_from_files(files: &[FileInfo]) -> String {
-    // Reuse existing logic but generate directly from FileInfo
-    let file_tuples: Vec<(FileInfo, String)> = files.iter()
-        .map(|f| (f.clone(), String::new()))
-        .collect();
-    generate_tree_structure(&file_tuples)
+fn print_timings(timings: &Timings) {
+    eprintln!("\nProgram Operation Execution Times (µs):");
+    eprintln!("-------------------------------------------");
+    eprintln!("Collect files:             {:>10}", timings.collect_files);
+    eprintln!("Read selected contents:    {:>10}", timings.read_contents);
+    eprintln!("Count tokens:              {:>10}", timings.count_tokens);
+    eprintln!("Generate tree structure:   {:>10}", timings.generate_tree);
+    eprintln!(
+        "Generate output string:    {:>10}",
+        timings.generate_output_string
+    );
+    eprintln!("Write to file:             {:>10}", timings.write_file);
+    eprintln!("-------------------------------------------");
+    eprintln!("Total processing time:     {:>10} µs", timings.total);
+    eprintln!(
+        "                           {:>10} ms (approx total)",
+        timings.total / 1000
+    );
+    eprintln!("-------------------------------------------");
 }
 
-fn generate_tree_structure(files: &[(FileInfo, String)]) -> String {
-    let mut path_map: HashMap<String, BTreeSet<String>> = HashMap::new();
-    let mut all_distinct_paths: HashSet<String> = HashSet::new();
-
-    for (file_info, _) in files {
-        let path = Path::new(&file_info.relative_path);
-        all_distinct_paths.insert(file_info.relative_path.clone());
-        let mut current_accumulated_path = PathBuf::new();
-        if let Some(parent_dir) = path.parent() {
-            for component in parent_dir.components() {
-                if let Some(comp_str) = component.as_os_str().to_str() {
-                    if comp_str != "." && comp_str != "/" {
-                        current_accumulated_path.push(comp_str);
-                        if !current_accumulated_path.as_os_str().is_empty() {
-                            all_distinct_paths.insert(
-                                current_accumulated_path
-                                    .to_string_lossy()
-                                    .replace('\\', "/"),
-                            );
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    for path_str in all_distinct_paths {
-        let p = Path::new(&path_str);
-        let file_name_os = p.file_name().unwrap_or(p.as_os_str());
-        let child_name = file_name_os.to_string_lossy().into_owned();
-
-        if let Some(parent_path_os) = p.parent() {
-            let parent_key = if parent_path_os.as_os_str().is_empty() {
-                ".".to_string()
-            } else {
-                parent_path_os.to_string_lossy().replace('\\', "/")
-            };
-            path_map.entry(parent_key).or_default().insert(child_name);
-        } else {
-            path_map
-                .entry(".".to_string())
-                .or_default()
-                .insert(child_name);
-        }
-    }
-    if files.is_empty()
-        && path_map
-            .get(".")
-            .is_none_or(std::collections::BTreeSet::is_empty)
+fn print_token_summary(files: &[FileInfo], plan: &TokenPlan, options: &TokenOptions) {
+    eprintln!("\nEstimated tokens ({}):", options.tokenizer.name());
+    eprintln!("-------------------------------------------");
+    for ((file_info, inclusion), tokens) in
+        files.iter().zip(&plan.inclusions).zip(&plan.file_tokens)
     {
-        return ".\n(No files or directories found to list)\n".to_string();
-    } else if files.len() == 1
-        && path_map.get(".").is_some_and(|s| s.len() == 1)
-        && path_map.get(".").unwrap().iter().next().unwrap() == &files[0].0.relative_path
-    {
-    } else if !path_map.contains_key(".") && !files.is_empty() {
-        for (file_info, _) in files {
-            let p = Path::new(&file_info.relative_path);
-            if p.parent().is_none_or(|par| par.as_os_str().is_empty()) {
-                path_map
-                    .entry(".".to_string())
-                    .or_default()
-                    .insert(p.file_name().unwrap().to_string_lossy().into_owned());
-            }
-        }
+        let note = match inclusion {
+            Inclusion::Full => String::new(),
+            Inclusion::Truncated { tokens: kept, .. } => format!(" (truncated to {kept})"),
+            Inclusion::Omitted => " (omitted)".to_string(),
+        };
+        eprintln!("{tokens:>10}  {}{note}", file_info.relative_path);
     }
-
-    let mut output_tree_string = String::new();
-    if path_map.contains_key(".") || !files.is_empty() {
-        output_tree_string.push_str(".\n");
-    }
-    generate_tree_recursive(&path_map, ".", "", &mut output_tree_string, true);
-    output_tree_string
-}
-
-fn generate_tree_recursive(
-    path_map: &HashMap<String, BTreeSet<String>>,
-    current_path_key: &str,
-    prefix_for_children_lines: &str,
-    output: &mut String,
-    is_current_path_conceptual_root: bool,
-) {
-    if let Some(children_names) = path_map.get(current_path_key) {
-        let num_children = children_names.len();
-        for (i, child_name) in children_names.iter().enumerate() {
-            let is_last = i == num_children - 1;
-
-            output.push_str(prefix_for_children_lines);
-            output.push_str(if is_last { "└── " } else { "├── " });
-            output.push_str(child_name);
-            output.push('\n');
-
-            let child_full_key = if is_current_path_conceptual_root && current_path_key == "." {
-                child_name.clone()
-            } else {
-                format!("{current_path_key}/{child_name}")
-            };
-
-            if path_map.contains_key(&child_full_key) {
-                let mut new_prefix_for_grandchildren = prefix_for_children_lines.to_string();
-                new_prefix_for_grandchildren.push_str(if is_last { "   " } else { "│  " });
-                generate_tree_recursive(
-                    path_map,
-                    &child_full_key,
-                    &new_prefix_for_grandchildren,
-                    output,
-                    false,
-                );
-            }
-        }
+    eprintln!("-------------------------------------------");
+    match options.max_tokens {
+        Some(max_tokens) => eprintln!(
+            "Total report tokens:       {:>10} of {max_tokens}",
+            plan.total
+        ),
+        None => eprintln!("Total report tokens:       {:>10}", plan.total),
     }
 }
@@ -0,0 +1,691 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{self, Read};
+use std::ops::Range;
+use std::slice;
+
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::cursor::Show;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+use rayon::prelude::*;
+
+use rosetree::cache::ScanCache;
+use rosetree::source::FileSource;
+use rosetree::tokens::{Tokenizer, measure};
+use rosetree::{FileInfo, build_path_map};
+
+/// Bytes of the highlighted file shown in the preview pane.
+const PREVIEW_BYTES: u64 = 64 * 1024;
+
+const HELP: &str = "↑↓ move  ←→ collapse/expand  space toggle  a all  / search  \
+                    PgUp/PgDn scroll preview  enter done  q quit";
+
+/// Let the user choose among `files` in a terminal tree view. Every file
+/// starts selected. Returns the chosen files in their original order, or
+/// `None` if the picker was closed without confirming.
+pub fn pick(
+    source: &dyn FileSource,
+    files: &[FileInfo],
+    tokenizer: &dyn Tokenizer,
+    cache: Option<&ScanCache>,
+) -> io::Result<Option<Vec<FileInfo>>> {
+    eprintln!(
+        "\nMeasuring {} files ({})...",
+        files.len(),
+        tokenizer.name()
+    );
+    let sizes = files
+        .par_iter()
+        .map(|f| {
+            measure(source, f, tokenizer, cache).map_or(FileSize::default(), |m| FileSize {
+                bytes: m.bytes,
+                tokens: m.tokens,
+            })
+        })
+        .collect();
+
+    let mut picker = Picker::new(source, files, sizes, tokenizer.name());
+    // Drawn on stderr like the other prompts, so stdout stays free for the report
+    let _guard = TerminalGuard::enter()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stderr()))?;
+    let confirmed = picker.run(&mut terminal)?;
+
+    Ok(confirmed.then(|| {
+        files
+            .iter()
+            .zip(&picker.selected)
+            .filter(|(_, selected)| **selected)
+            .map(|(file_info, _)| file_info.clone())
+            .collect()
+    }))
+}
+
+/// Raw mode on the alternate screen for as long as it lives, restored even
+/// when the picker returns early with an error.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        enable_raw_mode()?;
+        execute!(io::stderr(), EnterAlternateScreen)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stderr(), LeaveAlternateScreen, Show);
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct FileSize {
+    bytes: u64,
+    tokens: usize,
+}
+
+struct Node {
+    name: String,
+    depth: usize,
+    parent: Option<usize>,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    /// Child nodes and the positions in `Tree::order` of the files below
+    /// the directory.
+    Dir {
+        children: Vec<usize>,
+        files: Range<usize>,
+    },
+    File(usize),
+}
+
+/// The directory tree of the candidate files, built from the same path map
+/// as the report's tree.
+struct Tree {
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+    /// Node of each file, by file index.
+    file_nodes: Vec<usize>,
+    /// File indices in tree order, so the files below a directory are one
+    /// range of it.
+    order: Vec<usize>,
+}
+
+impl Tree {
+    fn build(files: &[FileInfo]) -> Self {
+        let path_map = build_path_map(files);
+        let index: HashMap<&str, usize> = files
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.relative_path.as_str(), i))
+            .collect();
+        let mut tree = Tree {
+            nodes: Vec::new(),
+            roots: Vec::new(),
+            file_nodes: vec![0; files.len()],
+            order: Vec::with_capacity(files.len()),
+        };
+        tree.roots = tree.add_children(&path_map, &index, ".", 0);
+        tree
+    }
+
+    fn add_children(
+        &mut self,
+        path_map: &HashMap<String, BTreeSet<String>>,
+        index: &HashMap<&str, usize>,
+        key: &str,
+        depth: usize,
+    ) -> Vec<usize> {
+        let Some(names) = path_map.get(key) else {
+            return Vec::new();
+        };
+        let mut ids = Vec::new();
+        for name in names {
+            let child_key = if key == "." {
+                name.clone()
+            } else {
+                format!("{key}/{name}")
+            };
+            let kind = if path_map.contains_key(&child_key) {
+                let start = self.order.len();
+                let children = self.add_children(path_map, index, &child_key, depth + 1);
+                NodeKind::Dir {
+                    children,
+                    files: start..self.order.len(),
+                }
+            } else if let Some(&file) = index.get(child_key.as_str()) {
+                self.order.push(file);
+                NodeKind::File(file)
+            } else {
+                continue;
+            };
+
+            let id = self.nodes.len();
+            match &kind {
+                NodeKind::Dir { children, .. } => {
+                    for &child in children {
+                        self.nodes[child].parent = Some(id);
+                    }
+                }
+                NodeKind::File(file) => self.file_nodes[*file] = id,
+            }
+            self.nodes.push(Node {
+                name: name.clone(),
+                depth,
+                parent: None,
+                kind,
+            });
+            ids.push(id);
+        }
+        ids
+    }
+
+    fn files_below(&self, id: usize) -> &[usize] {
+        match &self.nodes[id].kind {
+            NodeKind::Dir { files, .. } => &self.order[files.clone()],
+            NodeKind::File(file) => slice::from_ref(file),
+        }
+    }
+}
+
+struct Picker<'a> {
+    source: &'a dyn FileSource,
+    files: &'a [FileInfo],
+    sizes: Vec<FileSize>,
+    tokenizer_name: &'static str,
+    tree: Tree,
+    selected: Vec<bool>,
+    /// Selected files below each node, kept up to date with `selected`.
+    selected_below: Vec<usize>,
+    /// Total size of the files below each node.
+    bytes_below: Vec<u64>,
+    expanded: HashSet<usize>,
+    /// Nodes shown in the list: the expanded tree, or the files matching
+    /// the search query ranked by score.
+    rows: Vec<usize>,
+    list: ListState,
+    query: String,
+    typing: bool,
+    /// File shown in the preview pane and its first `PREVIEW_BYTES`.
+    preview: Option<(usize, String)>,
+    preview_scroll: u16,
+}
+
+impl<'a> Picker<'a> {
+    fn new(
+        source: &'a dyn FileSource,
+        files: &'a [FileInfo],
+        sizes: Vec<FileSize>,
+        tokenizer_name: &'static str,
+    ) -> Self {
+        let tree = Tree::build(files);
+        let expanded = tree
+            .roots
+            .iter()
+            .copied()
+            .filter(|&id| matches!(tree.nodes[id].kind, NodeKind::Dir { .. }))
+            .collect();
+        // Children come before their directory, so one pass adds them up
+        let mut bytes_below = vec![0; tree.nodes.len()];
+        for (id, node) in tree.nodes.iter().enumerate() {
+            bytes_below[id] = match &node.kind {
+                NodeKind::Dir { children, .. } => children.iter().map(|&c| bytes_below[c]).sum(),
+                &NodeKind::File(file) => sizes[file].bytes,
+            };
+        }
+        let selected_below = (0..tree.nodes.len())
+            .map(|id| tree.files_below(id).len())
+            .collect();
+        let mut picker = Picker {
+            source,
+            files,
+            sizes,
+            tokenizer_name,
+            tree,
+            selected: vec![true; files.len()],
+            selected_below,
+            bytes_below,
+            expanded,
+            rows: Vec::new(),
+            list: ListState::default(),
+            query: String::new(),
+            typing: false,
+            preview: None,
+            preview_scroll: 0,
+        };
+        picker.refresh_rows(None);
+        picker
+    }
+
+    /// Handle keys until the selection is confirmed (`true`) or the picker
+    /// is closed (`false`).
+    fn run(&mut self, terminal: &mut Terminal<CrosstermBackend<io::Stderr>>) -> io::Result<bool> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+                return Ok(false);
+            }
+
+            if self.typing {
+                match key.code {
+                    KeyCode::Char(c) => {
+                        self.query.push(c);
+                        self.refresh_rows(None);
+                    }
+                    KeyCode::Backspace => {
+                        self.query.pop();
+                        self.refresh_rows(None);
+                    }
+                    KeyCode::Enter => self.typing = false,
+                    KeyCode::Esc => {
+                        self.typing = false;
+                        self.clear_search();
+                    }
+                    KeyCode::Up => self.move_cursor(-1),
+                    KeyCode::Down => self.move_cursor(1),
+                    _ => {}
+                }
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => self.move_cursor(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.move_cursor(1),
+                KeyCode::Home => self.move_cursor(isize::MIN),
+                KeyCode::End => self.move_cursor(isize::MAX),
+                KeyCode::Left | KeyCode::Char('h') => self.collapse(),
+                KeyCode::Right | KeyCode::Char('l') => self.expand(),
+                KeyCode::Char(' ') => self.toggle_current(),
+                KeyCode::Char('a') => {
+                    let select = !self.selected.iter().all(|&s| s);
+                    self.select_all(select);
+                }
+                KeyCode::Char('/') => self.typing = true,
+                KeyCode::PageDown => self.preview_scroll = self.preview_scroll.saturating_add(10),
+                KeyCode::PageUp => self.preview_scroll = self.preview_scroll.saturating_sub(10),
+                KeyCode::Enter => return Ok(true),
+                KeyCode::Esc if !self.query.is_empty() => self.clear_search(),
+                KeyCode::Esc | KeyCode::Char('q') => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+
+    fn current(&self) -> Option<usize> {
+        self.list
+            .selected()
+            .and_then(|row| self.rows.get(row).copied())
+    }
+
+    /// Rebuild the rows and keep `keep` under the cursor when it is shown.
+    fn refresh_rows(&mut self, keep: Option<usize>) {
+        self.rows = if self.query.is_empty() {
+            let mut rows = Vec::new();
+            self.push_visible(&self.tree.roots, &mut rows);
+            rows
+        } else {
+            let matcher = SkimMatcherV2::default();
+            let mut scored: Vec<(i64, usize)> = self
+                .files
+                .iter()
+                .enumerate()
+                .filter_map(|(i, f)| {
+                    matcher
+                        .fuzzy_match(&f.relative_path, &self.query)
+                        .map(|score| (score, i))
+                })
+                .collect();
+            scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+            scored
+                .into_iter()
+                .map(|(_, file)| self.tree.file_nodes[file])
+                .collect()
+        };
+
+        let row = keep
+            .and_then(|id| self.rows.iter().position(|&r| r == id))
+            .or(self.list.selected())
+            .unwrap_or(0);
+        self.list.select(if self.rows.is_empty() {
+            None
+        } else {
+            Some(row.min(self.rows.len() - 1))
+        });
+    }
+
+    fn push_visible(&self, ids: &[usize], rows: &mut Vec<usize>) {
+        for &id in ids {
+            rows.push(id);
+            if let NodeKind::Dir { children, .. } = &self.tree.nodes[id].kind
+                && self.expanded.contains(&id)
+            {
+                self.push_visible(children, rows);
+            }
+        }
+    }
+
+    /// Leave the search and show the highlighted file in the tree.
+    fn clear_search(&mut self) {
+        let current = self.current();
+        let mut parent = current.and_then(|id| self.tree.nodes[id].parent);
+        while let Some(id) = parent {
+            self.expanded.insert(id);
+            parent = self.tree.nodes[id].parent;
+        }
+        self.query.clear();
+        self.refresh_rows(current);
+    }
+
+    fn move_cursor(&mut self, delta: isize) {
+        let Some(row) = self.list.selected() else {
+            return;
+        };
+        let last = self.rows.len().saturating_sub(1);
+        let row = row.saturating_add_signed(delta).min(last);
+        self.list.select(Some(row));
+        self.preview_scroll = 0;
+    }
+
+    fn expand(&mut self) {
+        if let Some(id) = self.current()
+            && self.query.is_empty()
+            && matches!(self.tree.nodes[id].kind, NodeKind::Dir { .. })
+        {
+            self.expanded.insert(id);
+            self.refresh_rows(Some(id));
+        }
+    }
+
+    /// Collapse the highlighted directory, or move to the parent directory.
+    fn collapse(&mut self) {
+        let Some(id) = self.current().filter(|_| self.query.is_empty()) else {
+            return;
+        };
+        if self.expanded.remove(&id) {
+            self.refresh_rows(Some(id));
+        } else if let Some(parent) = self.tree.nodes[id].parent {
+            self.refresh_rows(Some(parent));
+        }
+    }
+
+    /// Select every file below the highlighted node, or deselect them all if
+    /// they already are.
+    fn toggle_current(&mut self) {
+        let Some(id) = self.current() else {
+            return;
+        };
+        let count = self.tree.files_below(id).len();
+        let select = self.selected_below[id] < count;
+        for i in 0..count {
+            let file = self.tree.files_below(id)[i];
+            self.set_selected(file, select);
+        }
+    }
+
+    /// Select or deselect one file, updating the counts of the directories
+    /// above it.
+    fn set_selected(&mut self, file: usize, select: bool) {
+        if self.selected[file] == select {
+            return;
+        }
+        self.selected[file] = select;
+        let mut node = Some(self.tree.file_nodes[file]);
+        while let Some(id) = node {
+            if select {
+                self.selected_below[id] += 1;
+            } else {
+                self.selected_below[id] -= 1;
+            }
+            node = self.tree.nodes[id].parent;
+        }
+    }
+
+    fn select_all(&mut self, select: bool) {
+        self.selected.fill(select);
+        for (id, count) in self.selected_below.iter_mut().enumerate() {
+            *count = if select {
+                self.tree.files_below(id).len()
+            } else {
+                0
+            };
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status, help] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list_area, preview_area] =
+            Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)])
+                .areas(main);
+
+        let items: Vec<ListItem> = self
+            .rows
+            .iter()
+            .map(|&id| ListItem::new(self.row_line(id)))
+            .collect();
+        let title = if self.query.is_empty() {
+            " Files ".to_string()
+        } else {
+            format!(" Files matching {:?} ", self.query)
+        };
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.list);
+
+        let (title, text) = self.preview_text();
+        frame.render_widget(
+            Paragraph::new(text)
+                .block(Block::bordered().title(title))
+                .scroll((self.preview_scroll, 0)),
+            preview_area,
+        );
+
+        frame.render_widget(Paragraph::new(self.status_line()), status);
+        frame.render_widget(Paragraph::new(HELP.dim()), help);
+    }
+
+    fn row_line(&self, id: usize) -> Line<'static> {
+        let node = &self.tree.nodes[id];
+        let checkbox = match self.selected_below[id] {
+            0 => "[ ] ",
+            n if n == self.tree.files_below(id).len() => "[x] ",
+            _ => "[-] ",
+        };
+        let size = format_size(self.bytes_below[id]);
+
+        let mut spans = Vec::new();
+        match &node.kind {
+            NodeKind::File(file) if !self.query.is_empty() => {
+                spans.push(Span::raw(checkbox));
+                spans.push(Span::raw(self.files[*file].relative_path.clone()));
+            }
+            NodeKind::File(_) => {
+                spans.push(Span::raw("  ".repeat(node.depth)));
+                spans.push(Span::raw(format!("  {checkbox}")));
+                spans.push(Span::raw(node.name.clone()));
+            }
+            NodeKind::Dir { .. } => {
+                let arrow = if self.expanded.contains(&id) {
+                    "▾ "
+                } else {
+                    "▸ "
+                };
+                spans.push(Span::raw("  ".repeat(node.depth)));
+                spans.push(Span::raw(format!("{arrow}{checkbox}")));
+                spans.push(Span::raw(format!("{}/", node.name)).bold());
+            }
+        }
+        spans.push(Span::raw(format!("  {size}")).dim());
+        Line::from(spans)
+    }
+
+    fn preview_text(&mut self) -> (String, String) {
+        let Some(id) = self.current() else {
+            return (" Preview ".to_string(), String::new());
+        };
+        match &self.tree.nodes[id].kind {
+            NodeKind::Dir { files, .. } => (
+                format!(" {}/ ", self.tree.nodes[id].name),
+                format!(
+                    "{} files, {} selected",
+                    files.len(),
+                    self.selected_below[id]
+                ),
+            ),
+            &NodeKind::File(file) => {
+                if self
+                    .preview
+                    .as_ref()
+                    .is_none_or(|(shown, _)| *shown != file)
+                {
+                    let text = self.read_preview(&self.files[file]);
+                    self.preview = Some((file, text));
+                }
+                let text = self.preview.as_ref().map(|(_, t)| t.clone());
+                (
+                    format!(" {} ", self.files[file].relative_path),
+                    text.unwrap_or_default(),
+                )
+            }
+        }
+    }
+
+    fn read_preview(&self, file_info: &FileInfo) -> String {
+        let mut bytes = Vec::new();
+        match self.source.open(file_info) {
+            Ok(content) => {
+                if let Err(e) = content.take(PREVIEW_BYTES).read_to_end(&mut bytes) {
+                    return format!("Failed to read: {e}");
+                }
+            }
+            Err(e) => return format!("Failed to read: {e}"),
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    fn status_line(&self) -> Line<'static> {
+        let (count, total) = self
+            .selected
+            .iter()
+            .zip(&self.sizes)
+            .filter(|(selected, _)| **selected)
+            .fold((0, FileSize::default()), |(count, total), (_, size)| {
+                (
+                    count + 1,
+                    FileSize {
+                        bytes: total.bytes + size.bytes,
+                        tokens: total.tokens + size.tokens,
+                    },
+                )
+            });
+        let mut spans = vec![Span::raw(format!(
+            " {count} of {} files · {} · ~{} tokens ({})",
+            self.files.len(),
+            format_size(total.bytes),
+            total.tokens,
+            self.tokenizer_name
+        ))];
+        if self.typing {
+            spans.push(Span::raw(format!("   Search: {}▏", self.query)).bold());
+        }
+        Line::from(spans)
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{bytes} B")
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rosetree::source::DiskSource;
+
+    use super::*;
+
+    fn file(relative_path: &str) -> FileInfo {
+        FileInfo {
+            path: PathBuf::from(relative_path),
+            relative_path: relative_path.to_string(),
+            extension: String::new(),
+            diff: None,
+        }
+    }
+
+    fn node(tree: &Tree, name: &str) -> usize {
+        tree.nodes
+            .iter()
+            .position(|n| n.name == name)
+            .expect("node exists")
+    }
+
+    #[test]
+    fn directories_hold_the_range_of_their_files() {
+        let files = ["src/b.rs", "README.md", "src/util/c.rs", "src/a.rs"].map(file);
+        let tree = Tree::build(&files);
+        let mut below = tree.files_below(node(&tree, "src")).to_vec();
+        below.sort_unstable();
+        assert_eq!(below, [0, 2, 3]);
+        assert_eq!(tree.files_below(node(&tree, "util")), [2]);
+        assert_eq!(tree.files_below(node(&tree, "README.md")), [1]);
+    }
+
+    #[test]
+    fn selection_counts_follow_toggles() {
+        let files = ["src/a.rs", "src/util/b.rs", "src/util/c.rs", "README.md"].map(file);
+        let sizes = [1, 2, 4, 8].map(|bytes| FileSize { bytes, tokens: 0 });
+        let mut picker = Picker::new(&DiskSource, &files, sizes.to_vec(), "test");
+        let (src, util) = (node(&picker.tree, "src"), node(&picker.tree, "util"));
+        assert_eq!(picker.bytes_below[src], 7);
+        assert_eq!(picker.selected_below[src], 3);
+
+        picker.set_selected(1, false);
+        assert_eq!(picker.selected_below[util], 1);
+        assert_eq!(picker.selected_below[src], 2);
+
+        let row = picker.rows.iter().position(|&id| id == util);
+        picker.list.select(row);
+        picker.toggle_current();
+        assert_eq!(picker.selected_below[util], 2);
+        assert_eq!(picker.selected_below[src], 3);
+        picker.toggle_current();
+        assert_eq!(picker.selected_below[util], 0);
+        assert_eq!(picker.selected, [true, false, false, true]);
+
+        picker.select_all(true);
+        assert_eq!(picker.selected_below[src], 3);
+        assert!(picker.selected.iter().all(|&s| s));
+    }
+}
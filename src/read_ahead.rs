@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::num::NonZero;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// Files read ahead of the one being written, so memory stays bounded by
+/// this many buffered files however long the list is.
+pub const READ_AHEAD_FILES: usize = 32;
+
+/// Progress shared between the readers and the writer.
+struct Window {
+    /// Next item to hand to a reader.
+    next: usize,
+    /// Items taken by the writer so far.
+    written: usize,
+    /// Set when the writer is done or failed.
+    stop: bool,
+}
+
+/// Run `read` for every item on a few threads while `write` consumes the
+/// results on the calling thread in the order of `items`. Readers stay at
+/// most `window` items ahead of the writer, so at most that many results are
+/// held at once. Stops at the first error from `write`.
+pub fn read_ahead<T, R, E>(
+    items: &[T],
+    window: usize,
+    read: impl Fn(&T) -> R + Sync,
+    write: impl FnMut(&T, R) -> Result<(), E>,
+) -> Result<(), E>
+where
+    T: Sync,
+    R: Send,
+{
+    let threads = thread::available_parallelism().map_or(1, NonZero::get);
+    read_ahead_on(threads, items, window, read, write)
+}
+
+/// [`read_ahead`] with at most `threads` readers.
+fn read_ahead_on<T, R, E>(
+    threads: usize,
+    items: &[T],
+    window: usize,
+    read: impl Fn(&T) -> R + Sync,
+    mut write: impl FnMut(&T, R) -> Result<(), E>,
+) -> Result<(), E>
+where
+    T: Sync,
+    R: Send,
+{
+    let window = window.max(1);
+    let threads = threads.min(window).min(items.len());
+    if threads <= 1 {
+        for item in items {
+            write(item, read(item))?;
+        }
+        return Ok(());
+    }
+
+    let state = Mutex::new(Window {
+        next: 0,
+        written: 0,
+        stop: false,
+    });
+    let room = Condvar::new();
+    let lock = || state.lock().unwrap_or_else(PoisonError::into_inner);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (read, room, lock) = (&read, &room, &lock);
+            scope.spawn(move || {
+                loop {
+                    let index = {
+                        let mut state: MutexGuard<Window> = lock();
+                        while !state.stop
+                            && state.next < items.len()
+                            && state.next >= state.written + window
+                        {
+                            state = room.wait(state).unwrap_or_else(PoisonError::into_inner);
+                        }
+                        if state.stop || state.next >= items.len() {
+                            break;
+                        }
+                        state.next += 1;
+                        state.next - 1
+                    };
+                    // A panic stops the other readers too, so the writer sees the
+                    // channel close instead of waiting for this result
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| read(&items[index])))
+                        .unwrap_or_else(|payload| {
+                            lock().stop = true;
+                            room.notify_all();
+                            panic::resume_unwind(payload)
+                        });
+                    if sender.send((index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // Results arrive out of order; at most `window` of them wait here
+        let mut pending = HashMap::new();
+        let mut written = || {
+            for (index, item) in items.iter().enumerate() {
+                let result = loop {
+                    if let Some(result) = pending.remove(&index) {
+                        break result;
+                    }
+                    // Readers only stop early after a panic, which the scope
+                    // raises again once they are joined
+                    let Ok((done, result)) = receiver.recv() else {
+                        return Ok(());
+                    };
+                    pending.insert(done, result);
+                };
+                lock().written = index + 1;
+                room.notify_all();
+                write(item, result)?;
+            }
+            Ok(())
+        };
+        let result = written();
+        lock().stop = true;
+        room.notify_all();
+        result
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn results_are_written_in_order_within_the_window() {
+        let items: Vec<usize> = (0..200).collect();
+        let window = 4;
+        let taken = AtomicUsize::new(0);
+        let mut written = Vec::new();
+        let result: Result<(), ()> = read_ahead_on(
+            4,
+            &items,
+            window,
+            |&index| {
+                // The writer takes an item just before writing it, so a read
+                // may start one item past the window of the last write
+                assert!(index <= taken.load(Ordering::SeqCst) + window);
+                if index % 7 == 0 {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                index * 2
+            },
+            |&index, doubled| {
+                taken.fetch_add(1, Ordering::SeqCst);
+                assert_eq!(doubled, index * 2);
+                written.push(index);
+                Ok(())
+            },
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(written, items);
+    }
+
+    #[test]
+    fn the_first_write_error_stops_reading() {
+        let items: Vec<usize> = (0..1000).collect();
+        let read = AtomicUsize::new(0);
+        let mut written = 0;
+        let result = read_ahead_on(
+            4,
+            &items,
+            8,
+            |_| read.fetch_add(1, Ordering::SeqCst),
+            |&index, _| {
+                if index == 10 {
+                    return Err(index);
+                }
+                written += 1;
+                Ok(())
+            },
+        );
+        assert_eq!(result, Err(10));
+        assert_eq!(written, 10);
+        assert!(read.load(Ordering::SeqCst) < items.len());
+    }
+
+    #[test]
+    fn a_single_thread_reads_in_order() {
+        let items = ["a", "b", "c"];
+        let mut written = String::new();
+        let result: Result<(), ()> = read_ahead_on(
+            1,
+            &items,
+            32,
+            |item| item.to_uppercase(),
+            |_, upper| {
+                written.push_str(&upper);
+                Ok(())
+            },
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(written, "ABC");
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, SeekFrom, Write};
+use std::sync::LazyLock;
+
+use syntect::highlighting::ThemeSet;
+use syntect::html::{ClassStyle, css_for_theme_with_class_style, line_tokens_to_classed_spans};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+
+use super::{Budget, Content, OmittedFile, Renderer, Truncation, write_escaped};
+use crate::{FileInfo, REPORT_MARKER, SkippedFile, build_path_map, get_language_from_extension};
+
+/// Prefixed highlighting classes cannot collide with the report's own.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Theme whose colors are turned into the embedded highlighting CSS.
+const THEME_NAME: &str = "InspiredGitHub";
+
+/// Larger files are written without highlighting, which is slow on huge
+/// generated files and of little use there.
+const MAX_HIGHLIGHT_BYTES: u64 = 512 * 1024;
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+
+const STYLE: &str = r#"
+* { box-sizing: border-box; }
+body { margin: 0; display: flex; font: 14px/1.5 -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; color: #24292f; }
+nav { position: sticky; top: 0; height: 100vh; overflow: auto; flex: 0 0 300px; padding: 1em; border-right: 1px solid #d0d7de; background: #f6f8fa; }
+nav ul { list-style: none; margin: 0; padding-left: 1em; }
+nav > ul { padding-left: 0; }
+nav summary { cursor: pointer; }
+nav a { color: #0969da; text-decoration: none; }
+nav a:hover { text-decoration: underline; }
+main { flex: 1; min-width: 0; padding: 1em 2em; }
+h1, h2 { margin-top: 0; }
+.toolbar { margin-bottom: 1em; }
+.toolbar button { margin-right: 0.5em; }
+section.file { margin-bottom: 1em; border: 1px solid #d0d7de; border-radius: 6px; }
+section.file > details > summary { cursor: pointer; padding: 0.5em 1em; background: #f6f8fa; border-radius: 6px; }
+section.file .meta { color: #57606a; margin-left: 1em; }
+section.file .note { margin: 0; padding: 0.5em 1em; color: #57606a; font-style: italic; }
+pre { margin: 0; padding: 1em; overflow: auto; font: 12px/1.45 ui-monospace, SFMono-Regular, Menlo, Consolas, monospace; }
+:target > details > summary, li:target { background: #fff8c5; }
+"#;
+
+const SCRIPT: &str = r"
+document.querySelectorAll('[data-toggle]').forEach(function (button) {
+  button.addEventListener('click', function () {
+    var open = button.dataset.toggle === 'expand';
+    document.querySelectorAll('main details').forEach(function (d) { d.open = open; });
+  });
+});
+function reveal() {
+  var target = location.hash && document.getElementById(location.hash.slice(1));
+  var details = target && target.querySelector('details');
+  if (details) { details.open = true; }
+}
+window.addEventListener('hashchange', reveal);
+reveal();
+";
+
+/// A single offline HTML page: a clickable directory tree next to
+/// collapsible, syntax-highlighted file sections. Highlighting happens here,
+/// so the page needs no external assets.
+pub struct HtmlRenderer {
+    diff_only: bool,
+    /// Anchor number of every selected file, by relative path.
+    anchors: HashMap<String, usize>,
+}
+
+impl HtmlRenderer {
+    #[must_use]
+    pub fn new(diff_only: bool) -> Self {
+        HtmlRenderer {
+            diff_only,
+            anchors: HashMap::new(),
+        }
+    }
+
+    fn anchor(&self, file_info: &FileInfo) -> usize {
+        self.anchors
+            .get(&file_info.relative_path)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Renderer for HtmlRenderer {
+    fn begin(
+        &mut self,
+        out: &mut dyn Write,
+        _tree_structure: &str,
+        files: &[FileInfo],
+    ) -> io::Result<()> {
+        self.anchors = files
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.relative_path.clone(), i + 1))
+            .collect();
+
+        let themes = ThemeSet::load_defaults();
+        let theme_css = themes
+            .themes
+            .get(THEME_NAME)
+            .map(|theme| css_for_theme_with_class_style(theme, CLASS_STYLE))
+            .transpose()
+            .map_err(io::Error::other)?
+            .unwrap_or_default();
+
+        writeln!(out, "{REPORT_MARKER}")?;
+        writeln!(out, "<!DOCTYPE html>")?;
+        writeln!(out, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+        writeln!(out, "<meta name=\"generator\" content=\"rosetree\">")?;
+        writeln!(out, "<title>Project Analysis Report</title>")?;
+        writeln!(out, "<style>{STYLE}{theme_css}</style>\n</head>\n<body>")?;
+
+        writeln!(out, "<nav>\n<h2>File Structure</h2>\n<ul>")?;
+        let path_map = build_path_map(files);
+        write_tree(out, &path_map, ".", &self.anchors)?;
+        writeln!(out, "</ul>\n</nav>")?;
+
+        writeln!(out, "<main>\n<h1>Project Analysis Report</h1>")?;
+        writeln!(
+            out,
+            "<div class=\"toolbar\"><button data-toggle=\"expand\">Expand all</button><button data-toggle=\"collapse\">Collapse all</button></div>"
+        )
+    }
+
+    fn file(
+        &mut self,
+        out: &mut dyn Write,
+        file_info: &FileInfo,
+        content: &mut dyn Content,
+        truncation: Option<Truncation>,
+    ) -> io::Result<()> {
+        let size = content.seek(SeekFrom::End(0))?;
+        content.seek(SeekFrom::Start(0))?;
+        let language = get_language_from_extension(&file_info.extension);
+
+        writeln!(
+            out,
+            "<section class=\"file\" id=\"file-{}\">",
+            self.anchor(file_info)
+        )?;
+        write!(out, "<details open>\n<summary><code>")?;
+        write_escaped(out, &file_info.relative_path, escape)?;
+        write!(out, "</code><span class=\"meta\">")?;
+        if !language.is_empty() {
+            write!(out, "{language}, ")?;
+        }
+        writeln!(out, "{size} bytes</span></summary>")?;
+
+        if !(self.diff_only && file_info.diff.is_some()) {
+            write_code(out, content, size, language)?;
+            if let Some(truncation) = truncation {
+                writeln!(
+                    out,
+                    "<p class=\"note\">Truncated to {} of {} estimated tokens.</p>",
+                    truncation.kept_tokens, truncation.total_tokens
+                )?;
+            }
+        }
+        if let Some(diff) = &file_info.diff {
+            writeln!(out, "<p class=\"note\">Changes:</p>")?;
+            write_code(
+                out,
+                &mut io::Cursor::new(diff.as_bytes()),
+                diff.len() as u64,
+                "diff",
+            )?;
+        }
+        writeln!(out, "</details>\n</section>")
+    }
+
+    fn omitted(
+        &mut self,
+        out: &mut dyn Write,
+        budget: &Budget,
+        files: &[OmittedFile],
+    ) -> io::Result<()> {
+        writeln!(out, "<h2>Omitted Files</h2>")?;
+        write!(
+            out,
+            "<p>The following files were left out or truncated to fit the budget of {} tokens (",
+            budget.max_tokens
+        )?;
+        write_escaped(out, budget.tokenizer, escape)?;
+        writeln!(out, "):</p>\n<ul>")?;
+        for omitted in files {
+            // Left-out files have no section, so the tree links land here
+            if omitted.kept_tokens.is_none() {
+                write!(out, "<li id=\"file-{}\">", self.anchor(omitted.file_info))?;
+            } else {
+                write!(out, "<li>")?;
+            }
+            write!(out, "<code>")?;
+            write_escaped(out, &omitted.file_info.relative_path, escape)?;
+            let tokens = omitted.tokens;
+            match omitted.kept_tokens {
+                Some(kept) => {
+                    writeln!(out, "</code> (truncated, {kept} of {tokens} tokens)</li>")?;
+                }
+                None => writeln!(out, "</code> ({tokens} tokens)</li>")?,
+            }
+        }
+        writeln!(out, "</ul>")
+    }
+
+    fn skipped(&mut self, out: &mut dyn Write, files: &[SkippedFile]) -> io::Result<()> {
+        writeln!(out, "<h2>Skipped Files</h2>\n<ul>")?;
+        for skipped in files {
+            // Files that failed to read have no section either
+            match self.anchors.get(&skipped.relative_path) {
+                Some(anchor) if !skipped.is_dir => write!(out, "<li id=\"file-{anchor}\">")?,
+                _ => write!(out, "<li>")?,
+            }
+            write!(out, "<code>")?;
+            write_escaped(out, &skipped.relative_path, escape)?;
+            if skipped.is_dir {
+                write!(out, "/")?;
+            }
+            write!(out, "</code> (")?;
+            write_escaped(out, &skipped.error.to_string(), escape)?;
+            writeln!(out, ")</li>")?;
+        }
+        writeln!(out, "</ul>")
+    }
+
+    fn end(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "</main>\n<script>{SCRIPT}</script>\n</body>\n</html>")
+    }
+}
+
+/// Write the children of `key` as nested lists: directories collapse, files
+/// link to their section.
+fn write_tree(
+    out: &mut dyn Write,
+    path_map: &HashMap<String, BTreeSet<String>>,
+    key: &str,
+    anchors: &HashMap<String, usize>,
+) -> io::Result<()> {
+    let Some(children) = path_map.get(key) else {
+        return Ok(());
+    };
+    for name in children {
+        let child_key = if key == "." {
+            name.clone()
+        } else {
+            format!("{key}/{name}")
+        };
+        if path_map.contains_key(&child_key) {
+            write!(out, "<li><details open><summary>")?;
+            write_escaped(out, name, escape)?;
+            writeln!(out, "/</summary>\n<ul>")?;
+            write_tree(out, path_map, &child_key, anchors)?;
+            writeln!(out, "</ul></details></li>")?;
+        } else if let Some(anchor) = anchors.get(&child_key) {
+            write!(out, "<li><a href=\"#file-{anchor}\">")?;
+            write_escaped(out, name, escape)?;
+            writeln!(out, "</a></li>")?;
+        } else {
+            write!(out, "<li>")?;
+            write_escaped(out, name, escape)?;
+            writeln!(out, "</li>")?;
+        }
+    }
+    Ok(())
+}
+
+/// A `<pre>` block, highlighted unless the language is unknown or the
+/// content too large.
+fn write_code(
+    out: &mut dyn Write,
+    content: &mut dyn Content,
+    size: u64,
+    language: &str,
+) -> io::Result<()> {
+    write!(out, "<pre><code>")?;
+    match syntax_for(language) {
+        Some(syntax) if size <= MAX_HIGHLIGHT_BYTES => {
+            write_highlighted(out, content, syntax)?;
+        }
+        _ => {
+            let mut line = String::new();
+            while content.read_line(&mut line)? > 0 {
+                write_escaped(out, &line, escape)?;
+                line.clear();
+            }
+        }
+    }
+    writeln!(out, "</code></pre>")
+}
+
+/// Syntax for a language name from `get_language_from_extension`, or `None`
+/// when the bundled syntaxes have nothing better than plain text.
+fn syntax_for(language: &str) -> Option<&'static SyntaxReference> {
+    if language.is_empty() {
+        return None;
+    }
+    SYNTAXES.find_syntax_by_token(language)
+}
+
+/// Highlight line by line, carrying the parser state across lines so the
+/// content is streamed rather than held in memory.
+fn write_highlighted(
+    out: &mut dyn Write,
+    content: &mut dyn Content,
+    syntax: &SyntaxReference,
+) -> io::Result<()> {
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut open_spans: isize = 0;
+    let mut line = String::new();
+    while content.read_line(&mut line)? > 0 {
+        let ops = state
+            .parse_line(&line, &SYNTAXES)
+            .map_err(io::Error::other)?;
+        let (html, delta) = line_tokens_to_classed_spans(&line, &ops, CLASS_STYLE, &mut stack)
+            .map_err(io::Error::other)?;
+        out.write_all(html.as_bytes())?;
+        open_spans += delta;
+        line.clear();
+    }
+    for _ in 0..open_spans {
+        write!(out, "</span>")?;
+    }
+    Ok(())
+}
+
+/// Escapes for element content and double-quoted attribute values.
+fn escape(c: char) -> Option<&'static str> {
+    match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        _ => None,
+    }
+}
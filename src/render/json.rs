@@ -0,0 +1,247 @@
+use std::io::{self, Write};
+
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use super::{Budget, Content, OmittedFile, Renderer, Truncation, write_escaped};
+use crate::{FileInfo, JSON_REPORT_PREFIX, SkippedFile, get_language_from_extension};
+
+/// One JSON document holding the tree, then every file with its metadata and
+/// content, then any files the token budget left out.
+pub struct JsonRenderer {
+    diff_only: bool,
+    files_written: usize,
+    files_closed: bool,
+}
+
+impl JsonRenderer {
+    #[must_use]
+    pub fn new(diff_only: bool) -> Self {
+        JsonRenderer {
+            diff_only,
+            files_written: 0,
+            files_closed: false,
+        }
+    }
+}
+
+impl Renderer for JsonRenderer {
+    fn begin(
+        &mut self,
+        out: &mut dyn Write,
+        tree_structure: &str,
+        _files: &[FileInfo],
+    ) -> io::Result<()> {
+        write!(out, "{JSON_REPORT_PREFIX},\"tree\":")?;
+        serde_json::to_writer(&mut *out, tree_structure)?;
+        write!(out, ",\"files\":[")
+    }
+
+    fn file(
+        &mut self,
+        out: &mut dyn Write,
+        file_info: &FileInfo,
+        content: &mut dyn Content,
+        truncation: Option<Truncation>,
+    ) -> io::Result<()> {
+        if self.files_written > 0 {
+            write!(out, ",")?;
+        }
+        write_file_record(out, file_info, content, truncation, self.diff_only)?;
+        self.files_written += 1;
+        Ok(())
+    }
+
+    fn omitted(
+        &mut self,
+        out: &mut dyn Write,
+        budget: &Budget,
+        files: &[OmittedFile],
+    ) -> io::Result<()> {
+        let omitted: Vec<_> = files.iter().map(omitted_record).collect();
+        write!(out, "],\"budget\":")?;
+        serde_json::to_writer(
+            &mut *out,
+            &json!({ "max_tokens": budget.max_tokens, "tokenizer": budget.tokenizer }),
+        )?;
+        write!(out, ",\"omitted\":")?;
+        serde_json::to_writer(&mut *out, &omitted)?;
+        self.files_closed = true;
+        Ok(())
+    }
+
+    fn skipped(&mut self, out: &mut dyn Write, files: &[SkippedFile]) -> io::Result<()> {
+        if !self.files_closed {
+            write!(out, "]")?;
+            self.files_closed = true;
+        }
+        write!(out, ",\"skipped\":")?;
+        serde_json::to_writer(&mut *out, files)?;
+        Ok(())
+    }
+
+    fn end(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        if !self.files_closed {
+            write!(out, "]")?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+/// JSON Lines: a header record with the tree, then one self-contained record
+/// per file, so huge repositories can be consumed as a stream.
+pub struct JsonLinesRenderer {
+    diff_only: bool,
+}
+
+impl JsonLinesRenderer {
+    #[must_use]
+    pub fn new(diff_only: bool) -> Self {
+        JsonLinesRenderer { diff_only }
+    }
+}
+
+impl Renderer for JsonLinesRenderer {
+    fn begin(
+        &mut self,
+        out: &mut dyn Write,
+        tree_structure: &str,
+        _files: &[FileInfo],
+    ) -> io::Result<()> {
+        // A header record, so the report is recognised when scanned again
+        write!(out, "{JSON_REPORT_PREFIX},\"tree\":")?;
+        serde_json::to_writer(&mut *out, tree_structure)?;
+        writeln!(out, "}}")
+    }
+
+    fn file(
+        &mut self,
+        out: &mut dyn Write,
+        file_info: &FileInfo,
+        content: &mut dyn Content,
+        truncation: Option<Truncation>,
+    ) -> io::Result<()> {
+        write_file_record(out, file_info, content, truncation, self.diff_only)?;
+        writeln!(out)
+    }
+
+    fn omitted(
+        &mut self,
+        out: &mut dyn Write,
+        _budget: &Budget,
+        files: &[OmittedFile],
+    ) -> io::Result<()> {
+        for file in files.iter().filter(|f| f.kept_tokens.is_none()) {
+            serde_json::to_writer(&mut *out, &omitted_record(file))?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn skipped(&mut self, out: &mut dyn Write, files: &[SkippedFile]) -> io::Result<()> {
+        for file in files {
+            // Marked like the records of left-out files
+            let mut record = serde_json::to_value(file)?;
+            record["skipped"] = true.into();
+            serde_json::to_writer(&mut *out, &record)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn end(&mut self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Write one file object, streaming the content into a JSON string while
+/// hashing and counting it, so metadata that depends on the content follows
+/// the `content` field. With `diff_only`, a file's `diff` replaces its
+/// content and the metadata derived from it.
+fn write_file_record(
+    out: &mut dyn Write,
+    file_info: &FileInfo,
+    content: &mut dyn Content,
+    truncation: Option<Truncation>,
+    diff_only: bool,
+) -> io::Result<()> {
+    let language = get_language_from_extension(&file_info.extension);
+    write!(out, "{{\"relative_path\":")?;
+    serde_json::to_writer(&mut *out, &file_info.relative_path)?;
+    write!(out, ",\"extension\":")?;
+    serde_json::to_writer(&mut *out, &file_info.extension)?;
+    write!(out, ",\"language\":")?;
+    serde_json::to_writer(&mut *out, &(!language.is_empty()).then_some(language))?;
+    if !(diff_only && file_info.diff.is_some()) {
+        write_content_fields(out, content, truncation)?;
+    }
+    if let Some(diff) = &file_info.diff {
+        write!(out, ",\"diff\":")?;
+        serde_json::to_writer(&mut *out, diff.as_ref())?;
+    }
+    write!(out, "}}")
+}
+
+fn write_content_fields(
+    out: &mut dyn Write,
+    content: &mut dyn Content,
+    truncation: Option<Truncation>,
+) -> io::Result<()> {
+    write!(out, ",\"content\":\"")?;
+
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut lines = 0;
+    let mut line = String::new();
+    while content.read_line(&mut line)? > 0 {
+        write_escaped(out, &line, escape)?;
+        hasher.update(line.as_bytes());
+        size += line.len();
+        lines += 1;
+        line.clear();
+    }
+
+    write!(
+        out,
+        "\",\"size\":{size},\"lines\":{lines},\"sha256\":\"{:x}\"",
+        hasher.finalize()
+    )?;
+    if let Some(truncation) = truncation {
+        write!(
+            out,
+            ",\"truncated\":{{\"kept_tokens\":{},\"total_tokens\":{}}}",
+            truncation.kept_tokens, truncation.total_tokens
+        )?;
+    }
+    Ok(())
+}
+
+fn omitted_record(file: &OmittedFile) -> serde_json::Value {
+    json!({
+        "relative_path": file.file_info.relative_path,
+        "tokens": file.tokens,
+        "kept_tokens": file.kept_tokens,
+        "omitted": file.kept_tokens.is_none(),
+    })
+}
+
+/// `\u` escapes of the control characters without a shorter form.
+const CONTROL_ESCAPES: [&str; 32] = [
+    "\\u0000", "\\u0001", "\\u0002", "\\u0003", "\\u0004", "\\u0005", "\\u0006", "\\u0007",
+    "\\u0008", "\\u0009", "\\u000a", "\\u000b", "\\u000c", "\\u000d", "\\u000e", "\\u000f",
+    "\\u0010", "\\u0011", "\\u0012", "\\u0013", "\\u0014", "\\u0015", "\\u0016", "\\u0017",
+    "\\u0018", "\\u0019", "\\u001a", "\\u001b", "\\u001c", "\\u001d", "\\u001e", "\\u001f",
+];
+
+/// Escapes for the inside of a JSON string literal.
+fn escape(c: char) -> Option<&'static str> {
+    match c {
+        '"' => Some("\\\""),
+        '\\' => Some("\\\\"),
+        '\n' => Some("\\n"),
+        '\r' => Some("\\r"),
+        '\t' => Some("\\t"),
+        c if c < ' ' => Some(CONTROL_ESCAPES[c as usize]),
+        _ => None,
+    }
+}
@@ -0,0 +1,230 @@
+use std::io::{self, SeekFrom, Write};
+
+use super::{Budget, Content, OmittedFile, Renderer, Truncation};
+use crate::{FileInfo, REPORT_MARKER, SkippedFile, get_language_from_extension};
+
+/// Written after the closing fence of a file whose last line has no
+/// newline, since the fence needs one, so unpacking restores the file as it
+/// was.
+pub const NO_FINAL_NEWLINE: &str = "*No newline at end of file.*";
+
+/// The default report: a Markdown document with the file tree and one fenced
+/// code block per file.
+pub struct MarkdownRenderer {
+    diff_only: bool,
+}
+
+impl MarkdownRenderer {
+    #[must_use]
+    pub fn new(diff_only: bool) -> Self {
+        MarkdownRenderer { diff_only }
+    }
+}
+
+impl Renderer for MarkdownRenderer {
+    fn begin(
+        &mut self,
+        out: &mut dyn Write,
+        tree_structure: &str,
+        _files: &[FileInfo],
+    ) -> io::Result<()> {
+        write!(out, "{REPORT_MARKER}\n# Project Analysis Report\n\n")?;
+        write!(out, "## File Structure\n\n```\n{tree_structure}```\n\n")?;
+        write!(out, "## File Contents\n\n")
+    }
+
+    fn file(
+        &mut self,
+        out: &mut dyn Write,
+        file_info: &FileInfo,
+        content: &mut dyn Content,
+        truncation: Option<Truncation>,
+    ) -> io::Result<()> {
+        if !(self.diff_only && file_info.diff.is_some()) {
+            write_content_block(out, file_info, content, truncation)?;
+        }
+        if let Some(diff) = &file_info.diff {
+            // Labeled so that unpacking a report does not mistake it for content
+            let fence = fence_for(diff);
+            write!(
+                out,
+                "### `{}` (diff)\n\n{fence}diff\n{diff}",
+                file_info.relative_path
+            )?;
+            if !diff.is_empty() && !diff.ends_with('\n') {
+                writeln!(out)?;
+            }
+            out.write_all(file_footer(&fence).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn omitted(
+        &mut self,
+        out: &mut dyn Write,
+        budget: &Budget,
+        files: &[OmittedFile],
+    ) -> io::Result<()> {
+        write!(out, "## Omitted Files\n\n")?;
+        write!(
+            out,
+            "The following files were left out or truncated to fit the budget of {} tokens ({}):\n\n",
+            budget.max_tokens, budget.tokenizer
+        )?;
+        for omitted in files {
+            let path = &omitted.file_info.relative_path;
+            let tokens = omitted.tokens;
+            match omitted.kept_tokens {
+                Some(kept) => writeln!(out, "- `{path}` (truncated, {kept} of {tokens} tokens)")?,
+                None => writeln!(out, "- `{path}` ({tokens} tokens)")?,
+            }
+        }
+        writeln!(out)
+    }
+
+    fn skipped(&mut self, out: &mut dyn Write, files: &[SkippedFile]) -> io::Result<()> {
+        write_skipped(out, files)
+    }
+
+    fn end(&mut self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn write_content_block(
+    out: &mut dyn Write,
+    file_info: &FileInfo,
+    content: &mut dyn Content,
+    truncation: Option<Truncation>,
+) -> io::Result<()> {
+    // Pre-pass over the file to size the fence, keeping only a counter in
+    // memory, then rewind and stream the content
+    let mut scan = BacktickScan::default();
+    loop {
+        let chunk = content.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        scan.feed(chunk);
+        let len = chunk.len();
+        content.consume(len);
+    }
+    content.seek(SeekFrom::Start(0))?;
+    let fence = scan.fence();
+
+    out.write_all(file_heading(file_info, None, &fence).as_bytes())?;
+
+    // Stream read and write file content
+    let mut line = String::new();
+    let mut ends_with_newline = true;
+
+    while content.read_line(&mut line)? > 0 {
+        out.write_all(line.as_bytes())?;
+        ends_with_newline = line.ends_with('\n');
+        line.clear();
+    }
+
+    // The closing fence must start on its own line
+    if !ends_with_newline {
+        writeln!(out)?;
+    }
+    out.write_all(file_footer(&fence).as_bytes())?;
+
+    if let Some(truncation) = truncation {
+        write!(
+            out,
+            "*Truncated to {} of {} estimated tokens.*\n\n",
+            truncation.kept_tokens, truncation.total_tokens
+        )?;
+    } else if !ends_with_newline {
+        write!(out, "{NO_FINAL_NEWLINE}\n\n")?;
+    }
+    Ok(())
+}
+
+/// Tracks the longest run of backticks in a file, possibly fed in chunks, so
+/// its code fence can be made longer than anything inside it.
+#[derive(Default)]
+struct BacktickScan {
+    current: usize,
+    longest: usize,
+}
+
+impl BacktickScan {
+    fn feed(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if b == b'`' {
+                self.current += 1;
+                self.longest = self.longest.max(self.current);
+            } else {
+                self.current = 0;
+            }
+        }
+    }
+
+    /// A backtick fence that no run in the scanned content can close early.
+    fn fence(&self) -> String {
+        "`".repeat(self.longest.max(2) + 1)
+    }
+}
+
+/// The Skipped Files section, shared with split reports.
+///
+/// # Errors
+///
+/// Returns any error from writing to `out`.
+pub fn write_skipped(out: &mut dyn Write, files: &[SkippedFile]) -> io::Result<()> {
+    write!(out, "## Skipped Files\n\n")?;
+    for skipped in files {
+        let path = &skipped.relative_path;
+        let slash = if skipped.is_dir { "/" } else { "" };
+        writeln!(out, "- `{path}{slash}` ({})", skipped.error)?;
+    }
+    writeln!(out)
+}
+
+/// Fence for content that is already in memory.
+#[must_use]
+pub fn fence_for(content: &str) -> String {
+    let mut scan = BacktickScan::default();
+    scan.feed(content.as_bytes());
+    scan.fence()
+}
+
+/// Markdown heading and opening fence written before each file's content.
+/// `label` marks continuation blocks of a file split across report parts.
+#[must_use]
+pub fn file_heading(file_info: &FileInfo, label: Option<&str>, fence: &str) -> String {
+    // Determine syntax highlighting type based on extension
+    let language = get_language_from_extension(&file_info.extension);
+    let label = label.map(|l| format!(" ({l})")).unwrap_or_default();
+    format!(
+        "### `{}`{label}\n\n{fence}{language}\n",
+        file_info.relative_path
+    )
+}
+
+#[must_use]
+pub fn file_footer(fence: &str) -> String {
+    format!("{fence}\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fences_are_longer_than_any_backtick_run() {
+        assert_eq!(fence_for("plain"), "```");
+        assert_eq!(fence_for("a ``` fence"), "````");
+        assert_eq!(fence_for("````\ncode\n````\n"), "`````");
+    }
+
+    #[test]
+    fn runs_continue_across_fed_blocks() {
+        let mut scan = BacktickScan::default();
+        scan.feed(b"text ``");
+        scan.feed(b"``");
+        assert_eq!(scan.fence(), "`````");
+    }
+}
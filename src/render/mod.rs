@@ -0,0 +1,148 @@
+pub mod html;
+pub mod json;
+pub mod markdown;
+pub mod xml;
+
+use std::io::{self, BufRead, Seek, Write};
+
+use crate::config::OutputFormat;
+use crate::{FileInfo, SkippedFile};
+use xml::XmlOptions;
+
+/// File content handed to a renderer. Renderers may read it more than once
+/// by seeking back to the start.
+pub trait Content: BufRead + Seek {}
+
+impl<T: BufRead + Seek> Content for T {}
+
+/// A file that only partly fits the token budget.
+#[derive(Clone, Copy)]
+pub struct Truncation {
+    pub kept_tokens: usize,
+    pub total_tokens: usize,
+}
+
+/// A file left out of, or cut short in, a report with a token budget.
+pub struct OmittedFile<'a> {
+    pub file_info: &'a FileInfo,
+    pub tokens: usize,
+    /// Set when the file was truncated rather than left out entirely.
+    pub kept_tokens: Option<usize>,
+}
+
+/// The token budget a report was fitted into.
+pub struct Budget<'a> {
+    pub max_tokens: usize,
+    pub tokenizer: &'a str,
+}
+
+/// Writes a report piece by piece, so file contents can be streamed from
+/// disk instead of being held in memory.
+pub trait Renderer {
+    /// Everything that comes before the first file. `files` lists every file
+    /// selected for the report, including any the token budget leaves out.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from writing to `out`.
+    fn begin(
+        &mut self,
+        out: &mut dyn Write,
+        tree_structure: &str,
+        files: &[FileInfo],
+    ) -> io::Result<()>;
+
+    /// One file's content, cut short when `truncation` is given.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from reading `content` or writing to `out`.
+    fn file(
+        &mut self,
+        out: &mut dyn Write,
+        file_info: &FileInfo,
+        content: &mut dyn Content,
+        truncation: Option<Truncation>,
+    ) -> io::Result<()>;
+
+    /// Called after the last file when the token budget left files out.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from writing to `out`.
+    fn omitted(
+        &mut self,
+        out: &mut dyn Write,
+        budget: &Budget,
+        files: &[OmittedFile],
+    ) -> io::Result<()>;
+
+    /// Called before `end` when paths were skipped by the scan or failed to
+    /// read while writing the report.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from writing to `out`.
+    fn skipped(&mut self, out: &mut dyn Write, files: &[SkippedFile]) -> io::Result<()>;
+
+    /// Everything that comes after the last file.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from writing to `out`.
+    fn end(&mut self, out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// Where a file's git diff goes in a report limited to changed files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum DiffDisplay {
+    /// After the full file content
+    #[default]
+    Beside,
+    /// In place of the file content
+    Instead,
+}
+
+/// Format-specific settings, gathered from the command line and config.
+#[derive(Clone, Default)]
+pub struct RenderOptions {
+    pub xml: XmlOptions,
+    /// Write only the diff of files that carry one, not their content.
+    pub diff_only: bool,
+}
+
+impl OutputFormat {
+    #[must_use]
+    pub fn renderer(self, options: &RenderOptions) -> Box<dyn Renderer> {
+        match self {
+            OutputFormat::Markdown => Box::new(markdown::MarkdownRenderer::new(options.diff_only)),
+            OutputFormat::Json => Box::new(json::JsonRenderer::new(options.diff_only)),
+            OutputFormat::Jsonl => Box::new(json::JsonLinesRenderer::new(options.diff_only)),
+            OutputFormat::Xml => Box::new(xml::XmlRenderer::new(
+                options.xml.clone(),
+                options.diff_only,
+            )),
+            OutputFormat::Html => Box::new(html::HtmlRenderer::new(options.diff_only)),
+        }
+    }
+}
+
+/// Write `text` with every character that `escape` maps replaced by its
+/// escape, copying the runs between them through unchanged. Each format
+/// passes its own mapping.
+fn write_escaped(
+    out: &mut dyn Write,
+    text: &str,
+    escape: fn(char) -> Option<&'static str>,
+) -> io::Result<()> {
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if let Some(escaped) = escape(c) {
+            out.write_all(&text.as_bytes()[start..i])?;
+            out.write_all(escaped.as_bytes())?;
+            start = i + c.len_utf8();
+        }
+    }
+    out.write_all(&text.as_bytes()[start..])
+}
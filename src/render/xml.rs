@@ -0,0 +1,324 @@
+use std::io::{self, SeekFrom, Write};
+
+use serde::Deserialize;
+
+use super::{Budget, Content, OmittedFile, Renderer, Truncation, write_escaped};
+use crate::{FileInfo, SkippedFile, get_language_from_extension};
+
+/// Optional attributes of each document element.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum XmlAttribute {
+    /// Language derived from the file extension
+    Language,
+    /// Content size in bytes
+    Size,
+    /// 1-based position of the file in the report
+    Index,
+}
+
+/// Element names and content handling of the XML report, configurable in the
+/// `[xml]` table of `.rosetree.toml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct XmlOptions {
+    pub root_tag: String,
+    pub tree_tag: String,
+    pub document_tag: String,
+    pub source_tag: String,
+    pub content_tag: String,
+    pub diff_tag: String,
+    /// Wrap content in CDATA sections instead of escaping it.
+    pub cdata: bool,
+    pub attributes: Vec<XmlAttribute>,
+}
+
+impl Default for XmlOptions {
+    fn default() -> Self {
+        XmlOptions {
+            root_tag: "documents".to_string(),
+            tree_tag: "tree".to_string(),
+            document_tag: "document".to_string(),
+            source_tag: "source".to_string(),
+            content_tag: "content".to_string(),
+            diff_tag: "diff".to_string(),
+            cdata: false,
+            attributes: Vec::new(),
+        }
+    }
+}
+
+impl XmlOptions {
+    /// Reject tag names that would produce malformed XML.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message naming the first invalid tag.
+    pub fn validate(&self) -> Result<(), String> {
+        for tag in [
+            &self.root_tag,
+            &self.tree_tag,
+            &self.document_tag,
+            &self.source_tag,
+            &self.content_tag,
+            &self.diff_tag,
+        ] {
+            let mut chars = tag.chars();
+            let valid_start = chars
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+            if !valid_start || !chars.all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c)) {
+                return Err(format!("Invalid XML tag name: {tag:?}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wraps the tree and every file in XML elements, which content cannot break
+/// out of the way it can close a Markdown fence.
+pub struct XmlRenderer {
+    options: XmlOptions,
+    diff_only: bool,
+    files_written: usize,
+}
+
+impl XmlRenderer {
+    #[must_use]
+    pub fn new(options: XmlOptions, diff_only: bool) -> Self {
+        XmlRenderer {
+            options,
+            diff_only,
+            files_written: 0,
+        }
+    }
+}
+
+impl Renderer for XmlRenderer {
+    fn begin(
+        &mut self,
+        out: &mut dyn Write,
+        tree_structure: &str,
+        _files: &[FileInfo],
+    ) -> io::Result<()> {
+        let o = &self.options;
+        writeln!(out, "<{} generator=\"rosetree\">", o.root_tag)?;
+        write!(out, "<{}>", o.tree_tag)?;
+        write_escaped(out, tree_structure, escape)?;
+        writeln!(out, "</{}>", o.tree_tag)
+    }
+
+    fn file(
+        &mut self,
+        out: &mut dyn Write,
+        file_info: &FileInfo,
+        content: &mut dyn Content,
+        truncation: Option<Truncation>,
+    ) -> io::Result<()> {
+        let o = &self.options;
+        self.files_written += 1;
+
+        write!(out, "<{} path=\"", o.document_tag)?;
+        write_escaped(out, &file_info.relative_path, escape)?;
+        write!(out, "\"")?;
+        for attribute in &o.attributes {
+            match attribute {
+                XmlAttribute::Language => {
+                    let language = get_language_from_extension(&file_info.extension);
+                    if !language.is_empty() {
+                        write!(out, " language=\"{language}\"")?;
+                    }
+                }
+                XmlAttribute::Size => {
+                    let size = content.seek(SeekFrom::End(0))?;
+                    content.seek(SeekFrom::Start(0))?;
+                    write!(out, " size=\"{size}\"")?;
+                }
+                XmlAttribute::Index => write!(out, " index=\"{}\"", self.files_written)?,
+            }
+        }
+        if let Some(truncation) = truncation {
+            write!(
+                out,
+                " truncated_tokens=\"{}\" total_tokens=\"{}\"",
+                truncation.kept_tokens, truncation.total_tokens
+            )?;
+        }
+        writeln!(out, ">")?;
+
+        write!(out, "<{}>", o.source_tag)?;
+        write_escaped(out, &file_info.relative_path, escape)?;
+        writeln!(out, "</{}>", o.source_tag)?;
+
+        if !(self.diff_only && file_info.diff.is_some()) {
+            write!(out, "<{}>", o.content_tag)?;
+            if o.cdata {
+                write!(out, "<![CDATA[")?;
+            }
+            let mut line = String::new();
+            while content.read_line(&mut line)? > 0 {
+                write_text(out, &line, o.cdata)?;
+                line.clear();
+            }
+            if o.cdata {
+                write!(out, "]]>")?;
+            }
+            writeln!(out, "</{}>", o.content_tag)?;
+        }
+        if let Some(diff) = &file_info.diff {
+            write!(out, "<{}>", o.diff_tag)?;
+            if o.cdata {
+                write!(out, "<![CDATA[")?;
+            }
+            write_text(out, diff, o.cdata)?;
+            if o.cdata {
+                write!(out, "]]>")?;
+            }
+            writeln!(out, "</{}>", o.diff_tag)?;
+        }
+        writeln!(out, "</{}>", o.document_tag)
+    }
+
+    fn omitted(
+        &mut self,
+        out: &mut dyn Write,
+        budget: &Budget,
+        files: &[OmittedFile],
+    ) -> io::Result<()> {
+        write!(
+            out,
+            "<omitted max_tokens=\"{}\" tokenizer=\"",
+            budget.max_tokens
+        )?;
+        write_escaped(out, budget.tokenizer, escape)?;
+        writeln!(out, "\">")?;
+        for omitted in files {
+            write!(out, "<file path=\"")?;
+            write_escaped(out, &omitted.file_info.relative_path, escape)?;
+            write!(out, "\" tokens=\"{}\"", omitted.tokens)?;
+            if let Some(kept) = omitted.kept_tokens {
+                write!(out, " kept_tokens=\"{kept}\"")?;
+            }
+            writeln!(out, "/>")?;
+        }
+        writeln!(out, "</omitted>")
+    }
+
+    fn skipped(&mut self, out: &mut dyn Write, files: &[SkippedFile]) -> io::Result<()> {
+        writeln!(out, "<skipped>")?;
+        for skipped in files {
+            let tag = if skipped.is_dir { "directory" } else { "file" };
+            write!(out, "<{tag} path=\"")?;
+            write_escaped(out, &skipped.relative_path, escape)?;
+            write!(out, "\" reason=\"{}\">", skipped.error.code())?;
+            write_escaped(out, &skipped.error.to_string(), escape)?;
+            writeln!(out, "</{tag}>")?;
+        }
+        writeln!(out, "</skipped>")
+    }
+
+    fn end(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "</{}>", self.options.root_tag)
+    }
+}
+
+/// Characters XML 1.0 does not allow even when escaped are replaced.
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r') || c >= ' '
+}
+
+/// Escapes for element content and double-quoted attribute values.
+fn escape(c: char) -> Option<&'static str> {
+    match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        c if !is_xml_char(c) => Some("\u{fffd}"),
+        _ => None,
+    }
+}
+
+/// Write a piece of element content, inside an open CDATA section or escaped.
+fn write_text(out: &mut dyn Write, text: &str, cdata: bool) -> io::Result<()> {
+    if cdata {
+        write_cdata(out, text)
+    } else {
+        write_escaped(out, text, escape)
+    }
+}
+
+/// Write text inside an open CDATA section. `]]>` would end the section, so
+/// it is split across two sections.
+fn write_cdata(out: &mut dyn Write, text: &str) -> io::Result<()> {
+    let mut rest = text;
+    while let Some(i) = rest.find("]]>") {
+        write_xml_chars(out, &rest[..i + 2])?;
+        write!(out, "]]><![CDATA[")?;
+        rest = &rest[i + 2..];
+    }
+    write_xml_chars(out, rest)
+}
+
+fn write_xml_chars(out: &mut dyn Write, text: &str) -> io::Result<()> {
+    if text.chars().all(is_xml_char) {
+        return out.write_all(text.as_bytes());
+    }
+    let cleaned: String = text
+        .chars()
+        .map(|c| if is_xml_char(c) { c } else { '\u{fffd}' })
+        .collect();
+    out.write_all(cleaned.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn content_element(text: &str, cdata: bool) -> String {
+        let options = XmlOptions {
+            cdata,
+            ..XmlOptions::default()
+        };
+        let file_info = FileInfo {
+            path: PathBuf::from("a.txt"),
+            relative_path: "a.txt".to_string(),
+            extension: "txt".to_string(),
+            diff: None,
+        };
+        let mut out = Vec::new();
+        XmlRenderer::new(options, false)
+            .file(
+                &mut out,
+                &file_info,
+                &mut io::Cursor::new(text.as_bytes()),
+                None,
+            )
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let start = out.find("<content>").unwrap() + "<content>".len();
+        let end = out.find("</content>").unwrap();
+        out[start..end].to_string()
+    }
+
+    #[test]
+    fn cdata_sections_are_split_around_their_end_marker() {
+        let text = "a]]>b]]]>c\n";
+        let element = content_element(text, true);
+        assert_eq!(element, "<![CDATA[a]]]]><![CDATA[>b]]]]]><![CDATA[>c\n]]>");
+        // Joined back together, the sections hold the text unchanged
+        let joined: String = element
+            .split("<![CDATA[")
+            .map(|section| section.strip_suffix("]]>").unwrap_or(section))
+            .collect();
+        assert_eq!(joined, text);
+    }
+
+    #[test]
+    fn escaped_content_has_no_end_marker_to_split() {
+        assert_eq!(content_element("a]]>b\n", false), "a]]&gt;b\n");
+    }
+}
@@ -0,0 +1,394 @@
+use std::io::{self, Read, Write};
+
+use rayon::prelude::*;
+
+use crate::config::OutputFormat;
+use crate::read_ahead::{READ_AHEAD_FILES, read_ahead};
+use crate::render::{Budget, Content, OmittedFile, RenderOptions, Renderer, Truncation};
+use crate::source::FileSource;
+use crate::tokens::{Inclusion, TokenOptions, TokenPlan};
+use crate::{Error, FileInfo, Result, SkippedFile};
+
+/// Files up to this size are read ahead into memory; larger ones are
+/// streamed from their source when they are written.
+pub const MAX_BUFFERED_FILE: u64 = 1024 * 1024;
+
+/// Token options of a report together with the plan made from them by
+/// [`plan_tokens`].
+#[derive(Clone, Copy)]
+pub struct TokenBudget<'a> {
+    pub options: &'a TokenOptions,
+    pub plan: &'a TokenPlan,
+}
+
+/// Files written to a report and files that failed to read.
+pub struct ReportSummary {
+    pub files_processed: usize,
+    /// Paths skipped by the scan, listed in the report first.
+    pub skipped: Vec<SkippedFile>,
+    /// Files that failed to read while the report was written, listed after
+    /// the paths skipped by the scan.
+    pub failed: Vec<SkippedFile>,
+}
+
+/// Write `files` into `writer` in the given format. See
+/// [`write_report_with`], which takes any [`Renderer`].
+///
+/// # Errors
+///
+/// Returns [`Error::Write`](crate::Error::Write) if writing to `writer` fails.
+#[allow(clippy::too_many_arguments)]
+pub fn write_report<W: Write>(
+    writer: W,
+    source: &dyn FileSource,
+    files: &[FileInfo],
+    tree_structure: &str,
+    format: OutputFormat,
+    render_options: &RenderOptions,
+    budget: Option<TokenBudget>,
+    skipped: Vec<SkippedFile>,
+) -> Result<ReportSummary> {
+    let mut renderer = format.renderer(render_options);
+    write_report_with(
+        writer,
+        source,
+        files,
+        tree_structure,
+        renderer.as_mut(),
+        budget,
+        skipped,
+    )
+}
+
+/// Write `files` through `renderer` into `writer` in order while the files after them are read
+/// in parallel, at most [`READ_AHEAD_FILES`] ahead. Files larger than
+/// [`MAX_BUFFERED_FILE`] are streamed when their turn comes instead, so
+/// memory stays bounded. With a token budget, files are truncated or left
+/// out as its plan decides and the omissions are listed at the end of the
+/// report.
+///
+/// Files that fail to read are added to `skipped`, the paths the scan left
+/// out, and all of them are listed in a Skipped Files section at the end.
+///
+/// # Errors
+///
+/// Returns [`Error::Write`](crate::Error::Write) if writing to `writer` fails.
+pub fn write_report_with<W: Write>(
+    mut writer: W,
+    source: &dyn FileSource,
+    files: &[FileInfo],
+    tree_structure: &str,
+    renderer: &mut dyn Renderer,
+    budget: Option<TokenBudget>,
+    mut skipped: Vec<SkippedFile>,
+) -> Result<ReportSummary> {
+    renderer.begin(&mut writer, tree_structure, files)?;
+
+    let mut files_processed = 0;
+    // Read failures are listed after the paths the scan left out
+    let scanned = skipped.len();
+
+    let items: Vec<(&FileInfo, Inclusion)> = files
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f, budget.map_or(Inclusion::Full, |b| b.plan.inclusions[i])))
+        .collect();
+    let read = |&(file_info, inclusion): &(&FileInfo, Inclusion)| match inclusion {
+        Inclusion::Omitted => Ok(None),
+        _ => read_buffered(source, file_info),
+    };
+    read_ahead(
+        items.as_slice(),
+        READ_AHEAD_FILES,
+        read,
+        |&(file_info, inclusion), content| {
+            // Only reading the file counts against it; failing to write is
+            // the report's error and ends it
+            let content = match content
+                .and_then(|content| open_source(source, file_info, inclusion, budget, content))
+            {
+                Ok(Some(content)) => content,
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    skipped.push(SkippedFile {
+                        relative_path: file_info.relative_path.clone(),
+                        is_dir: false,
+                        error: e.into(),
+                    });
+                    return Ok(());
+                }
+            };
+            match content {
+                Source::Truncated { text, truncation } => {
+                    renderer.file(
+                        &mut writer,
+                        file_info,
+                        &mut io::Cursor::new(text.as_bytes()),
+                        Some(truncation),
+                    )?;
+                }
+                Source::Reader(mut content) => {
+                    renderer.file(&mut writer, file_info, content.as_mut(), None)?;
+                }
+            }
+            files_processed += 1;
+            Ok::<_, Error>(())
+        },
+    )?;
+
+    if let Some(TokenBudget { options, plan }) = budget
+        && let Some(max_tokens) = options.max_tokens
+        && plan.has_omissions()
+    {
+        let omitted: Vec<OmittedFile> = files
+            .iter()
+            .zip(&plan.inclusions)
+            .zip(&plan.file_tokens)
+            .filter_map(|((file_info, inclusion), &tokens)| match inclusion {
+                Inclusion::Full => None,
+                Inclusion::Truncated { tokens: kept, .. } => Some(OmittedFile {
+                    file_info,
+                    tokens,
+                    kept_tokens: Some(*kept),
+                }),
+                Inclusion::Omitted => Some(OmittedFile {
+                    file_info,
+                    tokens,
+                    kept_tokens: None,
+                }),
+            })
+            .collect();
+        let budget = Budget {
+            max_tokens,
+            tokenizer: options.tokenizer.name(),
+        };
+        renderer.omitted(&mut writer, &budget, &omitted)?;
+    }
+
+    if !skipped.is_empty() {
+        renderer.skipped(&mut writer, &skipped)?;
+    }
+    renderer.end(&mut writer)?;
+    writer.flush()?;
+
+    let failed = skipped.split_off(scanned);
+    Ok(ReportSummary {
+        files_processed,
+        skipped,
+        failed,
+    })
+}
+
+/// Count tokens for every file and fit them into the budget. The report
+/// overhead is measured by rendering the header and an empty block per file
+/// with a scratch renderer of the same format.
+#[must_use]
+pub fn plan_tokens(
+    source: &dyn FileSource,
+    files: &[FileInfo],
+    tree_structure: &str,
+    format: OutputFormat,
+    render_options: &RenderOptions,
+    options: &TokenOptions,
+) -> TokenPlan {
+    let tokenizer = options.tokenizer.as_ref();
+    let mut scratch = format.renderer(render_options);
+    let mut header = Vec::new();
+    // Writing to a Vec cannot fail
+    let _ = scratch.begin(&mut header, tree_structure, files);
+    let wrappers: Vec<Vec<u8>> = files
+        .iter()
+        .map(|f| {
+            let mut wrapper = Vec::new();
+            let _ = scratch.file(&mut wrapper, f, &mut io::Cursor::new(&[][..]), None);
+            wrapper
+        })
+        .collect();
+    let wrapper_tokens: Vec<usize> = wrappers
+        .par_iter()
+        .map(|w| tokenizer.count(&String::from_utf8_lossy(w)))
+        .collect();
+    let header_tokens = tokenizer.count(&String::from_utf8_lossy(&header));
+    TokenPlan::new(source, files, options, header_tokens, &wrapper_tokens)
+}
+
+/// The whole content of a file, or `None` when it is too large to buffer.
+/// Either way the content is checked to be UTF-8 first, so a file that only
+/// turns out not to be text past its first bytes fails before anything of it
+/// is written.
+fn read_buffered(source: &dyn FileSource, file_info: &FileInfo) -> io::Result<Option<Vec<u8>>> {
+    let mut reader = source.open(file_info)?;
+    let mut content = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_BUFFERED_FILE + 1)
+        .read_to_end(&mut content)?;
+    if content.len() as u64 <= MAX_BUFFERED_FILE {
+        std::str::from_utf8(&content).map_err(invalid_utf8)?;
+        return Ok(Some(content));
+    }
+    check_utf8(reader, content)?;
+    Ok(None)
+}
+
+/// Check that `pending` followed by the rest of `reader` is UTF-8, holding
+/// only a block of it at a time.
+fn check_utf8(mut reader: impl Read, mut pending: Vec<u8>) -> io::Result<()> {
+    let mut block = vec![0; 64 * 1024];
+    loop {
+        match std::str::from_utf8(&pending) {
+            Ok(_) => pending.clear(),
+            // A character cut at the end of the block continues in the next
+            Err(e) if e.error_len().is_none() => {
+                pending.drain(..e.valid_up_to());
+            }
+            Err(e) => return Err(invalid_utf8(e)),
+        }
+        let read = reader.read(&mut block)?;
+        if read == 0 {
+            return match std::str::from_utf8(&pending) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(invalid_utf8(e)),
+            };
+        }
+        pending.extend_from_slice(&block[..read]);
+    }
+}
+
+fn invalid_utf8(e: std::str::Utf8Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// The content of a file about to be written: the part of it the token
+/// budget keeps, or a reader over all of it.
+enum Source<'a> {
+    Truncated {
+        text: String,
+        truncation: Truncation,
+    },
+    Reader(Box<dyn Content + 'a>),
+}
+
+/// The content of a file as its inclusion needs it, from what was buffered
+/// or, when it was too large to buffer, from `source` again. The file is
+/// opened before anything is rendered so a failed file leaves no trace.
+/// `None` for a file the budget leaves out.
+fn open_source<'a>(
+    source: &'a dyn FileSource,
+    file_info: &FileInfo,
+    inclusion: Inclusion,
+    budget: Option<TokenBudget<'a>>,
+    content: Option<Vec<u8>>,
+) -> io::Result<Option<Source<'a>>> {
+    Ok(Some(match (inclusion, budget, content) {
+        (Inclusion::Omitted, ..) => return Ok(None),
+        (Inclusion::Truncated { tokens, bytes }, Some(budget), content) => {
+            let mut text = match content {
+                Some(content) => {
+                    String::from_utf8(content).map_err(|e| invalid_utf8(e.utf8_error()))?
+                }
+                None => source.read_to_string(file_info)?,
+            };
+            let truncation = Truncation {
+                kept_tokens: tokens,
+                total_tokens: budget.options.tokenizer.count(&text),
+            };
+            // The plan picked the cut; a file changed since may be shorter
+            text.truncate(text.floor_char_boundary(bytes));
+            Source::Truncated { text, truncation }
+        }
+        (_, _, Some(content)) => Source::Reader(Box::new(io::Cursor::new(content))),
+        (_, _, None) => Source::Reader(source.open(file_info)?),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+
+    use super::*;
+
+    /// Files named `missing` fail to open; every other file holds its name.
+    struct Names;
+
+    impl FileSource for Names {
+        fn open(&self, file_info: &FileInfo) -> io::Result<Box<dyn Content + '_>> {
+            if file_info.relative_path == "missing" {
+                return Err(io::ErrorKind::NotFound.into());
+            }
+            Ok(Box::new(io::Cursor::new(
+                file_info.relative_path.clone().into_bytes(),
+            )))
+        }
+
+        fn modified(&self, _: &FileInfo) -> Option<SystemTime> {
+            None
+        }
+    }
+
+    /// Accepts `left` bytes, then fails every write.
+    struct Full {
+        left: usize,
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::ErrorKind::StorageFull.into());
+            }
+            let n = buf.len().min(self.left);
+            self.left -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn files(names: &[&str]) -> Vec<FileInfo> {
+        names
+            .iter()
+            .map(|name| FileInfo {
+                path: PathBuf::from(name),
+                relative_path: (*name).to_string(),
+                extension: String::new(),
+                diff: None,
+            })
+            .collect()
+    }
+
+    fn report(writer: impl Write, names: &[&str]) -> Result<ReportSummary> {
+        write_report(
+            writer,
+            &Names,
+            &files(names),
+            "",
+            OutputFormat::Markdown,
+            &RenderOptions::default(),
+            None,
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn only_files_that_fail_to_read_are_failures() {
+        let mut out = Vec::new();
+        let summary = report(&mut out, &["a", "missing", "b"]).unwrap();
+        assert_eq!(summary.files_processed, 2);
+        assert!(summary.skipped.is_empty());
+        let failed: Vec<&str> = summary
+            .failed
+            .iter()
+            .map(|f| f.relative_path.as_str())
+            .collect();
+        assert_eq!(failed, ["missing"]);
+    }
+
+    #[test]
+    fn a_write_error_ends_the_report() {
+        let result = report(Full { left: 200 }, &["a"; 1000]);
+        assert!(matches!(result, Err(Error::Write(_))));
+    }
+}
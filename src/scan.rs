@@ -0,0 +1,704 @@
+use std::fs;
+use std::io::{BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::{WalkBuilder, WalkState};
+use rayon::prelude::*;
+
+use crate::attributes::{DiskAttributes, Mark, MarkedFiles};
+use crate::cache::ScanCache;
+use crate::config::RSTIGNORE_FILE_NAME;
+use crate::git::{self, ChangeSet};
+use crate::source::{DiskSource, FileSource};
+use crate::text::{check_utf8_file, is_known_text_extension};
+use crate::{
+    Error, FileError, FileInfo, JSON_REPORT_PREFIX, REPORT_MARKER, Result, SkippedFile,
+    relative_path,
+};
+
+/// Builds a scan of a directory tree, or of a git revision of it.
+///
+/// By default `.gitignore` rules apply, files marked generated or vendored
+/// in `.gitattributes` are left out, and so are earlier reports.
+pub struct Scanner {
+    root: PathBuf,
+    gitignore: bool,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    marked: MarkedFiles,
+    include_reports: bool,
+    output: Option<PathBuf>,
+    rev: Option<String>,
+    changes: Option<ChangeSet>,
+    with_diffs: bool,
+    max_file_size: Option<u64>,
+    cache: Option<Arc<ScanCache>>,
+}
+
+/// Files found by a scan and where to read their contents from.
+pub struct Scan {
+    /// Text files in no particular order.
+    pub files: Vec<FileInfo>,
+    pub source: Box<dyn FileSource>,
+    /// Paths left out because they are not text, are over the size limit or
+    /// could not be read, in no particular order.
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// Paths found by [`Scanner::walk`], not yet checked to be text. Lets the
+/// caller decide on `.gitignore` rules before any file is opened.
+pub struct Walk {
+    root: PathBuf,
+    found: Found,
+    skipped: Vec<SkippedFile>,
+    /// `.gitignore` files met while walking the working tree, sorted by
+    /// path. Empty for a walk of a git revision.
+    pub gitignore_files: Vec<GitIgnoreInfo>,
+}
+
+enum Found {
+    /// Working tree paths, checked by [`Scanner::classify`].
+    Candidates(Vec<PathBuf>),
+    /// Files at a revision, checked while their blobs were listed.
+    Checked(Vec<FileInfo>, Box<dyn FileSource>),
+}
+
+impl Scanner {
+    #[must_use]
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Scanner {
+            root: root.into(),
+            gitignore: true,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            marked: MarkedFiles::default(),
+            include_reports: false,
+            output: None,
+            rev: None,
+            changes: None,
+            with_diffs: false,
+            max_file_size: None,
+            cache: None,
+        }
+    }
+
+    /// Apply `.gitignore` rules while walking the tree.
+    #[must_use]
+    pub fn gitignore(mut self, gitignore: bool) -> Self {
+        self.gitignore = gitignore;
+        self
+    }
+
+    /// Only scan files matching this glob (gitignore syntax).
+    #[must_use]
+    pub fn include(mut self, glob: impl Into<String>) -> Self {
+        self.include.push(glob.into());
+        self
+    }
+
+    /// Skip files and directories matching this glob (gitignore syntax).
+    /// Excludes take precedence over includes.
+    #[must_use]
+    pub fn exclude(mut self, glob: impl Into<String>) -> Self {
+        self.exclude.push(glob.into());
+        self
+    }
+
+    /// Scan files marked `linguist-generated` or `linguist-vendored` in
+    /// `.gitattributes`.
+    #[must_use]
+    pub fn include_marked(mut self, marked: MarkedFiles) -> Self {
+        self.marked = marked;
+        self
+    }
+
+    /// Keep earlier rosetree reports found in the tree.
+    #[must_use]
+    pub fn include_reports(mut self, include_reports: bool) -> Self {
+        self.include_reports = include_reports;
+        self
+    }
+
+    /// The report about to be written, left out if it lies inside the tree.
+    #[must_use]
+    pub fn output(mut self, path: impl Into<PathBuf>) -> Self {
+        self.output = Some(path.into());
+        self
+    }
+
+    /// Read the files tracked at this commit, branch or tag instead of the
+    /// working tree. `.gitignore` rules are implied.
+    #[must_use]
+    pub fn rev(mut self, rev: impl Into<String>) -> Self {
+        self.rev = Some(rev.into());
+        self
+    }
+
+    /// Only keep files touched by `changes`, with their unified diff in
+    /// [`FileInfo::diff`] when `with_diffs` is set.
+    #[must_use]
+    pub fn changes(mut self, changes: ChangeSet, with_diffs: bool) -> Self {
+        self.changes = Some(changes);
+        self.with_diffs = with_diffs;
+        self
+    }
+
+    /// Leave out files larger than `bytes`, recording them as skipped.
+    #[must_use]
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    /// Take the text verdict of unchanged files from `cache` instead of
+    /// reading them, and record the verdicts of new and changed files. Not
+    /// used for a scan of a git revision.
+    #[must_use]
+    pub fn cache(mut self, cache: Arc<ScanCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Walk the tree and collect its text files.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the root is not an accessible directory, a glob is invalid,
+    /// or the git repository or revision needed by [`Scanner::rev`] or
+    /// [`Scanner::changes`] cannot be read.
+    pub fn scan(&self) -> Result<Scan> {
+        self.classify(self.walk()?)
+    }
+
+    /// Walk the tree without opening any file, listing the paths that pass
+    /// the ignore rules and globs. At a revision the files are checked as
+    /// well, since their blobs are at hand.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`Scanner::scan`], except for reading the changes.
+    pub fn walk(&self) -> Result<Walk> {
+        let root = self.root.canonicalize().map_err(|source| Error::Io {
+            path: self.root.clone(),
+            source,
+        })?;
+        if !root.is_dir() {
+            return Err(Error::NotADirectory(root));
+        }
+        let overrides = build_overrides(&root, &self.include, &self.exclude)?;
+
+        if let Some(rev) = &self.rev {
+            let (files, skipped, source) =
+                git::collect_files_at_rev(&root, rev, &overrides, self.marked, self.max_file_size)?;
+            return Ok(Walk {
+                root,
+                found: Found::Checked(files, Box::new(source)),
+                skipped,
+                gitignore_files: Vec::new(),
+            });
+        }
+        let collector = Collector {
+            base_dir: &root,
+            overrides: &overrides,
+            candidates: Mutex::new(Vec::new()),
+            skipped: Mutex::new(Vec::new()),
+            gitignore_files: Mutex::new(Vec::new()),
+        };
+        if self.gitignore {
+            collector.with_gitignore();
+        } else {
+            collector.without_gitignore();
+        }
+        let candidates = collector
+            .candidates
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        let skipped = collector
+            .skipped
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut gitignore_files = collector
+            .gitignore_files
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        gitignore_files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+        Ok(Walk {
+            root,
+            found: Found::Candidates(candidates),
+            skipped,
+            gitignore_files,
+        })
+    }
+
+    /// Check which paths of `walk` are text, then apply the report and
+    /// changed-file filters. `walk` may come from a scanner that differs
+    /// from this one in its `.gitignore` setting only.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the changes needed by [`Scanner::changes`] cannot be read.
+    pub fn classify(&self, walk: Walk) -> Result<Scan> {
+        let root = walk.root;
+        let (mut files, mut skipped, source) = match walk.found {
+            Found::Checked(files, source) => (files, walk.skipped, source),
+            Found::Candidates(candidates) => {
+                let attributes = DiskAttributes::on_disk(&root, self.marked);
+                let checker = Checker {
+                    base_dir: &root,
+                    attributes: &attributes,
+                    max_file_size: self.max_file_size,
+                    cache: self.cache.as_deref(),
+                    skipped: Mutex::new(walk.skipped),
+                };
+                let files = candidates
+                    .par_iter()
+                    .filter_map(|path| checker.check_file(path))
+                    .collect();
+                // Invalid lines are recorded against their file and left out
+                for (path, err) in attributes.take_errors() {
+                    checker.skip(&path, false, err.into());
+                }
+                let skipped = checker
+                    .skipped
+                    .into_inner()
+                    .unwrap_or_else(PoisonError::into_inner);
+                (files, skipped, Box::new(DiskSource) as Box<dyn FileSource>)
+            }
+        };
+        if !self.include_reports {
+            let output_path = self.output.as_deref().map(absolute_output_path);
+            files = exclude_reports(source.as_ref(), files, output_path.as_deref());
+        }
+        if let Some(changes) = &self.changes {
+            let changed = git::changed_files(&root, changes, self.with_diffs)?;
+            files = files
+                .into_iter()
+                .filter_map(|mut file_info| {
+                    file_info.diff = changed.get(&file_info.relative_path)?.clone();
+                    Some(file_info)
+                })
+                .collect();
+            skipped.retain(|s| s.is_dir || changed.contains_key(&s.relative_path));
+        }
+        Ok(Scan {
+            files,
+            source,
+            skipped,
+        })
+    }
+}
+
+/// A `.gitignore` file found below the scanned root.
+#[derive(Clone, Debug)]
+pub struct GitIgnoreInfo {
+    pub relative_path: String,
+}
+
+/// Build the `--include`/`--exclude` matcher. Globs follow the same rules as
+/// `WalkBuilder` overrides; excludes are added last so they take precedence.
+fn build_overrides(
+    base_dir: &Path,
+    includes: &[String],
+    excludes: &[String],
+) -> Result<Override, ignore::Error> {
+    let mut builder = OverrideBuilder::new(base_dir);
+    for glob in includes {
+        builder.add(glob)?;
+    }
+    for glob in excludes {
+        builder.add(&format!("!{glob}"))?;
+    }
+    builder.build()
+}
+
+/// What the collectors share while walking the working tree: the filters,
+/// the paths found and those left out so far with the reason for each, and
+/// the `.gitignore` files met on the way.
+struct Collector<'a> {
+    base_dir: &'a Path,
+    overrides: &'a Override,
+    candidates: Mutex<Vec<PathBuf>>,
+    skipped: Mutex<Vec<SkippedFile>>,
+    gitignore_files: Mutex<Vec<GitIgnoreInfo>>,
+}
+
+/// Checks the paths found by a walk, recording those left out.
+struct Checker<'a> {
+    base_dir: &'a Path,
+    attributes: &'a DiskAttributes,
+    max_file_size: Option<u64>,
+    cache: Option<&'a ScanCache>,
+    skipped: Mutex<Vec<SkippedFile>>,
+}
+
+impl Collector<'_> {
+    /// Walk the tree once on several threads, classifying each entry by the
+    /// file type its directory listing reports, so plain files and
+    /// directories are never stat'ed again.
+    fn with_gitignore(&self) {
+        // Applied as an entry filter rather than through `WalkBuilder::overrides`,
+        // where `--include` globs would whitelist files that .gitignore excludes
+        let entry_overrides = self.overrides.clone();
+        let walker = WalkBuilder::new(self.base_dir)
+            .git_ignore(true)
+            .git_global(true)
+            .git_exclude(true)
+            .parents(true)
+            .ignore(true)
+            .hidden(false)
+            .follow_links(false)
+            .add_custom_ignore_filename(RSTIGNORE_FILE_NAME)
+            .filter_entry(move |e| {
+                // Files are matched once their `.gitignore` files are noted
+                let is_dir = e.file_type().is_some_and(|ft| ft.is_dir());
+                e.file_name() != ".git"
+                    && !(is_dir && entry_overrides.matched(e.path(), true).is_ignore())
+            })
+            .build_parallel();
+
+        walker.run(|| {
+            Box::new(|result| {
+                let entry = match result {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        self.skip_walk_error(err);
+                        return WalkState::Continue;
+                    }
+                };
+                let path = entry.path();
+                let is_file = match entry.file_type() {
+                    Some(ft) if ft.is_dir() => return WalkState::Continue,
+                    Some(ft) if ft.is_file() => true,
+                    // Symlinks are not followed, but one to a file is read
+                    // like the file and a broken one is recorded as skipped
+                    Some(ft) if ft.is_symlink() => {
+                        if path.is_dir() {
+                            return WalkState::Continue;
+                        }
+                        false
+                    }
+                    _ => return WalkState::Continue,
+                };
+                if is_file && entry.file_name() == ".gitignore" {
+                    self.found_gitignore(path);
+                }
+                if self.overrides.matched(path, false).is_ignore() {
+                    return WalkState::Continue;
+                }
+                self.found(path);
+                WalkState::Continue
+            })
+        });
+    }
+
+    fn without_gitignore(&self) {
+        self.collect_recursive(self.base_dir, &[]);
+    }
+
+    fn collect_recursive(&self, dir: &Path, rstignores: &[Arc<Gitignore>]) {
+        let entries_result = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                self.skip(dir, true, err.into());
+                return;
+            }
+        };
+
+        // `.rstignore` files apply to their directory and below, like `.ignore`
+        // files do in `with_gitignore`
+        let mut rstignores = rstignores.to_vec();
+        let rstignore_path = dir.join(RSTIGNORE_FILE_NAME);
+        if rstignore_path.is_file() {
+            let mut builder = GitignoreBuilder::new(dir);
+            if let Some(err) = builder.add(&rstignore_path) {
+                self.skip_walk_error(err);
+            }
+            match builder.build() {
+                Ok(matcher) => rstignores.push(Arc::new(matcher)),
+                Err(err) => self.skip_walk_error(err),
+            }
+        }
+
+        let entries: Vec<PathBuf> = entries_result
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .collect();
+
+        entries.into_par_iter().for_each(|path| {
+            if path.is_dir() {
+                if path.file_name().and_then(|n| n.to_str()) == Some(".git")
+                    || self.overrides.matched(&path, true).is_ignore()
+                    || is_rstignored(&rstignores, &path, true)
+                {
+                    return;
+                }
+                // Symlinks are followed here, so one pointing back up the tree
+                // would be walked until the path grows too long
+                if path.is_symlink() && links_to_ancestor(&path, dir) {
+                    self.skip(&path, true, FileError::SymlinkLoop);
+                    return;
+                }
+                self.collect_recursive(&path, &rstignores);
+            } else if path.is_file() || is_broken_symlink(&path) {
+                if path.file_name() == Some(std::ffi::OsStr::new(".gitignore"))
+                    && !path.is_symlink()
+                {
+                    self.found_gitignore(&path);
+                }
+                if !self.overrides.matched(&path, false).is_ignore()
+                    && !is_rstignored(&rstignores, &path, false)
+                {
+                    self.found(&path);
+                }
+            }
+        });
+    }
+
+    fn found(&self, path: &Path) {
+        self.candidates
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(path.to_path_buf());
+    }
+
+    fn found_gitignore(&self, path: &Path) {
+        let relative_path = relative_path(path, self.base_dir);
+        self.gitignore_files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(GitIgnoreInfo { relative_path });
+    }
+
+    fn skip(&self, path: &Path, is_dir: bool, error: FileError) {
+        push_skipped(&self.skipped, path, self.base_dir, is_dir, error);
+    }
+
+    /// Record an error from walking the tree or reading an ignore file
+    /// against the path it names, or the root when it names none.
+    fn skip_walk_error(&self, err: ignore::Error) {
+        if let ignore::Error::Partial(errs) = err {
+            for err in errs {
+                self.skip_walk_error(err);
+            }
+            return;
+        }
+        let path = error_path(&err).unwrap_or(self.base_dir).to_path_buf();
+        self.skip(&path, path.is_dir(), err.into());
+    }
+}
+
+impl Checker<'_> {
+    /// The `FileInfo` of a file that passes the size limit and the text
+    /// check, otherwise record why it is left out. Files marked generated or
+    /// vendored in `.gitattributes` are dropped without a record, like
+    /// ignored ones; files marked binary are recorded.
+    fn check_file(&self, path: &Path) -> Option<FileInfo> {
+        match self.attributes.skips(path) {
+            Some(Mark::Binary) => {
+                self.skip(path, false, FileError::MarkedBinary);
+                return None;
+            }
+            Some(Mark::Generated | Mark::Vendored) => return None,
+            None => {}
+        }
+        let relative_path = relative_path(path, self.base_dir);
+        if let Err(err) = self.check_content(path, &relative_path) {
+            self.skip(path, false, err);
+            return None;
+        }
+
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_string();
+        Some(FileInfo {
+            path: path.to_path_buf(),
+            relative_path,
+            extension,
+            diff: None,
+        })
+    }
+
+    /// Apply the size limit and the text check, taking the verdict from the
+    /// cache when the file has not changed since it was last checked.
+    fn check_content(&self, path: &Path, relative_path: &str) -> Result<(), FileError> {
+        let mut metadata = None;
+        if let Some(limit) = self.max_file_size {
+            let m = fs::metadata(path)?;
+            if m.len() > limit {
+                return Err(FileError::TooLarge {
+                    size: m.len(),
+                    limit,
+                });
+            }
+            metadata = Some(m);
+        }
+        // Known text extensions are settled without opening the file
+        let Some(cache) = self.cache.filter(|_| !is_known_text_extension(path)) else {
+            return check_utf8_file(path);
+        };
+        let Some(metadata) = metadata.or_else(|| fs::metadata(path).ok()) else {
+            return check_utf8_file(path);
+        };
+        if let Some(text) = cache.verdict(relative_path, &metadata) {
+            return if text {
+                Ok(())
+            } else {
+                Err(FileError::NotUtf8)
+            };
+        }
+        let checked = check_utf8_file(path);
+        // Read failures may be temporary and are checked again next time
+        if let Ok(()) | Err(FileError::NotUtf8) = checked {
+            cache.record_verdict(relative_path, &metadata, checked.is_ok());
+        }
+        checked
+    }
+
+    fn skip(&self, path: &Path, is_dir: bool, error: FileError) {
+        push_skipped(&self.skipped, path, self.base_dir, is_dir, error);
+    }
+}
+
+fn push_skipped(
+    skipped: &Mutex<Vec<SkippedFile>>,
+    path: &Path,
+    base_dir: &Path,
+    is_dir: bool,
+    error: FileError,
+) {
+    let skipped_file = SkippedFile {
+        relative_path: relative_path(path, base_dir),
+        is_dir,
+        error,
+    };
+    skipped
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(skipped_file);
+}
+
+fn error_path(err: &ignore::Error) -> Option<&Path> {
+    match err {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            error_path(err)
+        }
+        _ => None,
+    }
+}
+
+/// Dangling or looping symlinks are kept so they are recorded as skipped.
+fn is_broken_symlink(path: &Path) -> bool {
+    path.is_symlink() && fs::metadata(path).is_err()
+}
+
+/// Whether the directory symlink `link`, found in `dir`, resolves to `dir`
+/// or one of its parents.
+fn links_to_ancestor(link: &Path, dir: &Path) -> bool {
+    match (link.canonicalize(), dir.canonicalize()) {
+        (Ok(target), Ok(dir)) => dir.starts_with(target),
+        _ => false,
+    }
+}
+
+/// Resolve the output path the same way scanned paths are resolved, so the
+/// report being written can be recognised if it lies inside the scanned tree.
+fn absolute_output_path(output: &Path) -> PathBuf {
+    let absolute =
+        std::env::current_dir().map_or_else(|_| output.to_path_buf(), |dir| dir.join(output));
+    if let (Some(parent), Some(name)) = (absolute.parent(), absolute.file_name())
+        && let Ok(parent) = parent.canonicalize()
+    {
+        return parent.join(name);
+    }
+    absolute
+}
+
+fn exclude_reports(
+    source: &dyn FileSource,
+    files: Vec<FileInfo>,
+    output_path: Option<&Path>,
+) -> Vec<FileInfo> {
+    files
+        .into_par_iter()
+        .filter(|f| {
+            if output_path.is_some_and(|p| p == f.path) {
+                return false;
+            }
+            let is_report = f
+                .path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(is_report_filename)
+                || (matches!(
+                    f.extension.as_str(),
+                    "md" | "markdown" | "html" | "json" | "jsonl" | "xml"
+                ) && has_report_marker(source, f));
+            !is_report
+        })
+        .collect()
+}
+
+/// Matches the default report name `rosetree_YYYYMMDD_HHMMSS.<ext>` of any
+/// output format, including the `_partNN` suffix of split reports.
+fn is_report_filename(name: &str) -> bool {
+    let Some(mut stamp) = name
+        .strip_prefix("rosetree_")
+        .and_then(|rest| rest.split_once('.'))
+        .map(|(stamp, _)| stamp)
+    else {
+        return false;
+    };
+    if let Some((prefix, part)) = stamp.rsplit_once("_part")
+        && !part.is_empty()
+        && part.bytes().all(|b| b.is_ascii_digit())
+    {
+        stamp = prefix;
+    }
+    let bytes = stamp.as_bytes();
+    bytes.len() == 15
+        && bytes[8] == b'_'
+        && bytes[..8].iter().all(u8::is_ascii_digit)
+        && bytes[9..].iter().all(u8::is_ascii_digit)
+}
+
+/// Whether the file starts the way one of the report formats does: the
+/// marker comment of Markdown and HTML, the `generator` field that opens JSON
+/// documents and JSON Lines headers, or the `generator` attribute of the XML
+/// root element, whatever its tag.
+fn has_report_marker(source: &dyn FileSource, file_info: &FileInfo) -> bool {
+    let Ok(content) = source.open(file_info) else {
+        return false;
+    };
+    // JSON reports are a single line, so only its start is read
+    let mut first_line = Vec::new();
+    if content
+        .take(256)
+        .read_until(b'\n', &mut first_line)
+        .is_err()
+    {
+        return false;
+    }
+    let first_line = String::from_utf8_lossy(&first_line);
+    let first_line = first_line.trim_end();
+    first_line == REPORT_MARKER
+        || first_line.starts_with(JSON_REPORT_PREFIX)
+        || (first_line.starts_with('<') && first_line.ends_with(" generator=\"rosetree\">"))
+}
+
+/// The deepest `.rstignore` with a matching rule decides, so nested files can
+/// re-include paths ignored by a parent directory.
+fn is_rstignored(rstignores: &[Arc<Gitignore>], path: &Path, is_dir: bool) -> bool {
+    rstignores
+        .iter()
+        .rev()
+        .map(|matcher| matcher.matched(path, is_dir))
+        .find(|m| !m.is_none())
+        .is_some_and(|m| m.is_ignore())
+}
@@ -0,0 +1,83 @@
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+
+use rayon::prelude::*;
+
+use crate::{FileInfo, SkippedFile};
+
+/// Chooses which scanned files go into a report.
+#[derive(Clone, Debug, Default)]
+pub struct Selector {
+    /// `None` selects every extension.
+    extensions: Option<HashSet<String>>,
+}
+
+impl Selector {
+    /// Select every file.
+    #[must_use]
+    pub fn all() -> Self {
+        Selector { extensions: None }
+    }
+
+    /// Select files with one of these extensions, given with or without the
+    /// leading dot. An empty extension selects files without one.
+    #[must_use]
+    pub fn extensions<I, S>(extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let extensions = extensions
+            .into_iter()
+            .map(|e| e.as_ref().trim().trim_start_matches('.').to_string())
+            .collect();
+        Selector {
+            extensions: Some(extensions),
+        }
+    }
+
+    #[must_use]
+    pub fn matches(&self, file_info: &FileInfo) -> bool {
+        self.extensions
+            .as_ref()
+            .is_none_or(|extensions| extensions.contains(&file_info.extension))
+    }
+
+    /// Keep the selected files, sorted by path as reports list them.
+    #[must_use]
+    pub fn select(&self, files: Vec<FileInfo>) -> Vec<FileInfo> {
+        let mut selected: Vec<FileInfo> =
+            files.into_par_iter().filter(|f| self.matches(f)).collect();
+        selected.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+        selected
+    }
+
+    /// Keep the skipped paths that the selection would have included, and
+    /// every directory since its contents are unknown, sorted by path.
+    #[must_use]
+    pub fn select_skipped(&self, skipped: Vec<SkippedFile>) -> Vec<SkippedFile> {
+        let mut selected: Vec<SkippedFile> = skipped
+            .into_iter()
+            .filter(|s| {
+                let extension = Path::new(&s.relative_path)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("");
+                s.is_dir
+                    || self
+                        .extensions
+                        .as_ref()
+                        .is_none_or(|extensions| extensions.contains(extension))
+            })
+            .collect();
+        selected.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+        selected
+    }
+}
+
+/// Distinct extensions of `files`, sorted, as offered for selection.
+#[must_use]
+pub fn available_extensions(files: &[FileInfo]) -> Vec<String> {
+    let extensions: BTreeSet<&str> = files.iter().map(|f| f.extension.as_str()).collect();
+    extensions.into_iter().map(str::to_string).collect()
+}
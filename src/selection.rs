@@ -0,0 +1,87 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Answers of the last interactive run in a project, reused by `--last` or
+/// when the next run offers to.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Selection {
+    /// Scanned root the selection belongs to, for whoever reads the file.
+    pub root: PathBuf,
+    /// Answer to the gitignore prompt, if there were rules to ask about.
+    pub gitignore: Option<bool>,
+    pub all: bool,
+    pub extensions: Vec<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Selection {
+    /// The selection saved for `root`, if there is a readable one.
+    pub fn load(root: &Path) -> Option<Self> {
+        let path = state_path(root)?;
+        let contents = fs::read_to_string(&path).ok()?;
+        match toml::from_str(&contents) {
+            Ok(selection) => Some(selection),
+            Err(e) => {
+                eprintln!("Warning: Ignoring invalid {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Save the selection for `self.root`, replacing the previous one.
+    pub fn save(&self) -> io::Result<()> {
+        let path = state_path(&self.root)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = toml::to_string(self).map_err(io::Error::other)?;
+        fs::write(path, contents)
+    }
+
+    /// One-line summary shown when offering to reuse the selection.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(gitignore) = self.gitignore {
+            parts.push(format!(
+                "gitignore: {}",
+                if gitignore { "yes" } else { "no" }
+            ));
+        }
+        if self.all {
+            parts.push("extensions: all".to_string());
+        } else {
+            let extensions: Vec<&str> = self
+                .extensions
+                .iter()
+                .map(|e| if e.is_empty() { "no extension" } else { e })
+                .collect();
+            parts.push(format!("extensions: {}", extensions.join(", ")));
+        }
+        if !self.include.is_empty() {
+            parts.push(format!("include: {}", self.include.join(", ")));
+        }
+        if !self.exclude.is_empty() {
+            parts.push(format!("exclude: {}", self.exclude.join(", ")));
+        }
+        parts.join("; ")
+    }
+}
+
+/// `$XDG_STATE_HOME/rosetree/selections/<hash of root>.toml`, falling back
+/// to the local data directory on platforms without a state directory.
+fn state_path(root: &Path) -> Option<PathBuf> {
+    let dir = dirs::state_dir().or_else(dirs::data_local_dir)?;
+    let hash = Sha256::digest(root.to_string_lossy().as_bytes());
+    Some(
+        dir.join("rosetree")
+            .join("selections")
+            .join(format!("{hash:x}.toml")),
+    )
+}
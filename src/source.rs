@@ -0,0 +1,65 @@
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::time::SystemTime;
+
+use crate::FileInfo;
+use crate::render::Content;
+
+/// Where the contents of selected files are read from: the working tree on
+/// disk or, with `--rev`, a git revision.
+pub trait FileSource: Sync {
+    /// Open a file for reading. The content may be read more than once by
+    /// seeking back to the start.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file no longer exists or cannot be opened.
+    fn open(&self, file_info: &FileInfo) -> io::Result<Box<dyn Content + '_>>;
+
+    /// Modification time recorded for the file in archives.
+    fn modified(&self, file_info: &FileInfo) -> Option<SystemTime>;
+
+    /// The whole content of a file.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file cannot be opened or read.
+    fn read(&self, file_info: &FileInfo) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.open(file_info)?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// The whole content of a file as text.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file cannot be read or is not valid UTF-8.
+    fn read_to_string(&self, file_info: &FileInfo) -> io::Result<String> {
+        String::from_utf8(self.read(file_info)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Files in the working tree, read through `FileInfo::path`.
+pub struct DiskSource;
+
+impl FileSource for DiskSource {
+    fn open(&self, file_info: &FileInfo) -> io::Result<Box<dyn Content + '_>> {
+        Ok(Box::new(BufReader::new(fs::File::open(&file_info.path)?)))
+    }
+
+    fn modified(&self, file_info: &FileInfo) -> Option<SystemTime> {
+        fs::metadata(&file_info.path)
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    fn read(&self, file_info: &FileInfo) -> io::Result<Vec<u8>> {
+        fs::read(&file_info.path)
+    }
+
+    fn read_to_string(&self, file_info: &FileInfo) -> io::Result<String> {
+        fs::read_to_string(&file_info.path)
+    }
+}
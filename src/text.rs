@@ -0,0 +1,162 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use content_inspector::inspect;
+
+use crate::FileError;
+
+/// Whether the file at `path` is text worth including in a report, judged by
+/// its extension or else by inspecting its first 8 KB.
+#[must_use]
+pub fn is_utf8_file(path: &Path) -> bool {
+    check_utf8_file(path).is_ok()
+}
+
+/// Like [`is_utf8_file`], but tells why a file is left out: it is not text,
+/// or it could not be opened or read.
+///
+/// # Errors
+///
+/// Returns [`FileError::NotUtf8`] for binary files and the classified I/O
+/// error when the file cannot be read.
+pub fn check_utf8_file(path: &Path) -> Result<(), FileError> {
+    // First check by file extension for known text file types
+    if is_known_text_extension(path) {
+        return Ok(());
+    }
+
+    // For unknown extensions, perform content inspection
+    let mut file = fs::File::open(path)?;
+    // Read up to 8KB for better detection accuracy
+    let mut buffer = [0u8; 8192];
+    let bytes_read = file.read(&mut buffer)?;
+    if is_text_sample(&buffer[..bytes_read]) {
+        Ok(())
+    } else {
+        Err(FileError::NotUtf8)
+    }
+}
+
+/// Inspect the start of a file whose extension is not known to be text.
+#[must_use]
+pub fn is_text_sample(sample: &[u8]) -> bool {
+    if sample.is_empty() {
+        return true; // Empty files are considered text files
+    }
+
+    // Multiple checks for better accuracy:
+    // 1. Use content_inspector as primary check
+    if inspect(sample).is_text() {
+        return true;
+    }
+
+    // 2. Check if it's valid UTF-8 and contains mostly printable chars
+    if let Ok(text) = std::str::from_utf8(sample) {
+        return is_mostly_printable_text(text);
+    }
+
+    // 3. Final fallback: very small files with some text content
+    sample.len() < 256 && has_some_text_chars(sample)
+}
+
+/// Extensions and file names that are always text.
+#[must_use]
+pub fn is_known_text_extension(path: &Path) -> bool {
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        let ext_lower = ext.to_lowercase();
+        matches!(
+            ext_lower.as_str(),
+            // Programming languages
+            "rs" | "py" | "js" | "ts" | "jsx" | "tsx" | "java" | "c" | "cpp" | "cc" | "cxx" |
+            "h" | "hpp" | "cs" | "php" | "rb" | "go" | "kt" | "swift" | "scala" | "clj" |
+            "hs" | "elm" | "erl" | "ex" | "exs" | "ml" | "fs" | "vb" | "pas" | "pl" | "pm" |
+            "r" | "jl" | "m" | "mm" | "f" | "f90" | "f95" | "ada" | "d" | "nim" | "zig" |
+
+            // Web and markup
+            "html" | "htm" | "xml" | "xhtml" | "svg" | "css" | "scss" | "sass" | "less" |
+            "vue" | "svelte" | "astro" | "ejs" | "handlebars" | "hbs" |
+
+            // Data formats
+            "json" | "yaml" | "yml" | "toml" | "ini" | "cfg" | "conf" | "properties" |
+            "csv" | "tsv" | "txt" | "log" |
+
+            // Documentation
+            "md" | "markdown" | "rst" | "tex" | "latex" | "org" | "adoc" | "asciidoc" |
+
+            // Scripts and config
+            "sh" | "bash" | "zsh" | "fish" | "ps1" | "cmd" | "bat" | "dockerfile" |
+            "makefile" | "mk" | "cmake" | "ninja" | "gradle" | "ant" |
+
+            // Other common text files
+            "gitignore" | "gitattributes" | "editorconfig" | "prettierrc" | "eslintrc" |
+            "tsconfig" | "package" | "cargo" | "gemfile" | "pipfile" | "requirements" |
+            "license" | "readme" | "changelog" | "authors" | "contributors" | "todo"
+        )
+    } else {
+        // Files without extension that are commonly text files
+        if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
+            let filename_lower = filename.to_lowercase();
+            matches!(
+                filename_lower.as_str(),
+                "makefile"
+                    | "dockerfile"
+                    | "cmakelists.txt"
+                    | "readme"
+                    | "license"
+                    | "changelog"
+                    | "authors"
+                    | "contributors"
+                    | "todo"
+                    | "news"
+                    | "install"
+                    | "copying"
+                    | "notice"
+                    | "manifest"
+                    | "version"
+                    | "gemfile"
+                    | "rakefile"
+                    | "pipfile"
+                    | "procfile"
+                    | "vagrantfile"
+                    | "gruntfile"
+                    | "gulpfile"
+                    | ".gitignore"
+                    | ".gitattributes"
+                    | ".editorconfig"
+                    | ".prettierrc"
+                    | ".eslintrc"
+                    | ".babelrc"
+                    | ".npmrc"
+                    | ".yarnrc"
+            )
+        } else {
+            false
+        }
+    }
+}
+
+fn is_mostly_printable_text(text: &str) -> bool {
+    if text.is_empty() {
+        return true;
+    }
+
+    let total_chars = text.chars().count();
+    let printable_chars = text
+        .chars()
+        .filter(|&c| c.is_ascii_graphic() || c.is_ascii_whitespace() || !c.is_ascii())
+        .count();
+
+    // At least 85% of characters should be printable
+    printable_chars * 100 >= total_chars * 85
+}
+
+fn has_some_text_chars(data: &[u8]) -> bool {
+    let text_chars = data
+        .iter()
+        .filter(|&&b| b.is_ascii_alphabetic() || b.is_ascii_digit() || b.is_ascii_whitespace())
+        .count();
+
+    // For very small files, if at least 50% are text characters, consider it text
+    text_chars * 2 >= data.len()
+}
@@ -0,0 +1,369 @@
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+use rayon::prelude::*;
+use tiktoken_rs::CoreBPE;
+
+use crate::FileInfo;
+use crate::cache::ScanCache;
+use crate::source::FileSource;
+
+/// Counts tokens the way a model would see the report.
+pub trait Tokenizer: Sync {
+    fn name(&self) -> &'static str;
+
+    fn count(&self, text: &str) -> usize;
+
+    /// Byte length of the longest prefix of `text` that fits in `max_tokens`.
+    fn prefix_len(&self, text: &str, max_tokens: usize) -> usize;
+}
+
+/// The `cl100k_base` BPE, with the vocabulary embedded in the binary.
+pub struct Cl100k(&'static CoreBPE);
+
+impl Default for Cl100k {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cl100k {
+    #[must_use]
+    pub fn new() -> Self {
+        Cl100k(tiktoken_rs::cl100k_base_singleton())
+    }
+}
+
+impl Tokenizer for Cl100k {
+    fn name(&self) -> &'static str {
+        "cl100k"
+    }
+
+    fn count(&self, text: &str) -> usize {
+        self.0.encode_ordinary(text).len()
+    }
+
+    fn prefix_len(&self, text: &str, max_tokens: usize) -> usize {
+        let tokens = self.0.encode_ordinary(text);
+        if tokens.len() <= max_tokens {
+            return text.len();
+        }
+        // Tokens decode back to the input, so a decoded prefix is a prefix of
+        // `text`; drop tokens that end mid-character until it decodes
+        (0..=max_tokens)
+            .rev()
+            .find_map(|n| self.0.decode(tokens[..n].to_vec()).ok())
+            .map_or(0, |prefix| prefix.len())
+    }
+}
+
+/// Rough estimate of one token per four characters, for when speed matters
+/// more than accuracy.
+pub struct CharsPerToken;
+
+impl Tokenizer for CharsPerToken {
+    fn name(&self) -> &'static str {
+        "chars/4"
+    }
+
+    fn count(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(4)
+    }
+
+    fn prefix_len(&self, text: &str, max_tokens: usize) -> usize {
+        text.char_indices()
+            .nth(max_tokens.saturating_mul(4))
+            .map_or(text.len(), |(i, _)| i)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TokenizerKind {
+    /// BPE used by GPT-4 class models
+    #[default]
+    Cl100k,
+    /// Characters divided by four
+    Chars,
+}
+
+impl TokenizerKind {
+    #[must_use]
+    pub fn build(self) -> Box<dyn Tokenizer> {
+        match self {
+            TokenizerKind::Cl100k => Box::new(Cl100k::new()),
+            TokenizerKind::Chars => Box::new(CharsPerToken),
+        }
+    }
+}
+
+/// Decides which files give way when the report would exceed `--max-tokens`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum BudgetPolicy {
+    /// Keep files in path order and leave out any file that does not fit
+    #[default]
+    Skip,
+    /// Keep files in path order and truncate the first file that does not fit
+    Truncate,
+    /// Keep as many files as possible, smallest first
+    SmallestFirst,
+}
+
+pub struct TokenOptions {
+    pub tokenizer: Box<dyn Tokenizer>,
+    pub max_tokens: Option<usize>,
+    pub policy: BudgetPolicy,
+    /// Estimates of unchanged files from earlier runs. Only valid when the
+    /// files are read from the working tree.
+    pub cache: Option<Arc<ScanCache>>,
+}
+
+/// Size and token estimate of a file's content.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Measure {
+    pub bytes: u64,
+    pub tokens: usize,
+}
+
+/// Read a file and count its tokens, or take the estimate from `cache` when
+/// the file has not changed since it was last counted.
+///
+/// # Errors
+///
+/// Returns the error from reading the file.
+pub fn measure(
+    source: &dyn FileSource,
+    file_info: &FileInfo,
+    tokenizer: &dyn Tokenizer,
+    cache: Option<&ScanCache>,
+) -> io::Result<Measure> {
+    // Taken before reading, so a file changed meanwhile is counted again
+    let metadata = cache.and_then(|_| fs::metadata(&file_info.path).ok());
+    if let (Some(cache), Some(metadata)) = (cache, &metadata)
+        && let Some(stats) = cache.content(&file_info.relative_path, metadata)
+        && let Some(&tokens) = stats.tokens.get(tokenizer.name())
+    {
+        return Ok(Measure {
+            bytes: metadata.len(),
+            tokens,
+        });
+    }
+    let bytes = source.read(file_info)?;
+    let tokens = tokenizer.count(&String::from_utf8_lossy(&bytes));
+    if let (Some(cache), Some(metadata)) = (cache, &metadata) {
+        cache.record_content(
+            &file_info.relative_path,
+            metadata,
+            &bytes,
+            tokenizer.name(),
+            tokens,
+        );
+    }
+    Ok(Measure {
+        bytes: bytes.len() as u64,
+        tokens,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Inclusion {
+    Full,
+    /// Only the first `bytes` bytes of the file, `tokens` tokens of it, are
+    /// written.
+    Truncated {
+        tokens: usize,
+        bytes: usize,
+    },
+    Omitted,
+}
+
+/// Per-file token estimates and what the budget allows to be written.
+pub struct TokenPlan {
+    /// Estimated tokens of each file's full content, in report order.
+    pub file_tokens: Vec<usize>,
+    pub inclusions: Vec<Inclusion>,
+    /// Estimated tokens of everything that will be written.
+    pub total: usize,
+}
+
+impl TokenPlan {
+    /// Count every file and fit them into the budget. `overhead` is the cost
+    /// of the report header and `wrapper_tokens` the cost of each file's
+    /// heading and fences.
+    pub fn new(
+        source: &dyn FileSource,
+        files: &[FileInfo],
+        options: &TokenOptions,
+        overhead: usize,
+        wrapper_tokens: &[usize],
+    ) -> Self {
+        let tokenizer = options.tokenizer.as_ref();
+        let cache = options.cache.as_deref();
+        let file_tokens: Vec<usize> = files
+            .par_iter()
+            .map(|f| {
+                // Unreadable files cost nothing here; the writer reports them
+                measure(source, f, tokenizer, cache).map_or(0, |m| m.tokens)
+            })
+            .collect();
+
+        let cost = |i: usize| file_tokens[i] + wrapper_tokens[i];
+        let Some(max_tokens) = options.max_tokens else {
+            let total = overhead + (0..files.len()).map(cost).sum::<usize>();
+            return TokenPlan {
+                inclusions: vec![Inclusion::Full; files.len()],
+                file_tokens,
+                total,
+            };
+        };
+
+        let mut order: Vec<usize> = (0..files.len()).collect();
+        if options.policy == BudgetPolicy::SmallestFirst {
+            order.sort_by_key(|&i| cost(i));
+        }
+
+        let mut inclusions = vec![Inclusion::Omitted; files.len()];
+        let mut used = overhead;
+        for i in order {
+            let remaining = max_tokens.saturating_sub(used);
+            if cost(i) <= remaining {
+                inclusions[i] = Inclusion::Full;
+                used += cost(i);
+            } else if options.policy == BudgetPolicy::Truncate && remaining > wrapper_tokens[i] {
+                // Unreadable files are left to the writer to report
+                let Ok(text) = source.read_to_string(&files[i]) else {
+                    inclusions[i] = Inclusion::Full;
+                    continue;
+                };
+                let bytes = tokenizer.prefix_len(&text, remaining - wrapper_tokens[i]);
+                let tokens = tokenizer.count(&text[..bytes]);
+                inclusions[i] = Inclusion::Truncated { tokens, bytes };
+                used += wrapper_tokens[i] + tokens;
+                // Only the first file that does not fit is truncated
+                break;
+            }
+        }
+
+        TokenPlan {
+            file_tokens,
+            inclusions,
+            total: used,
+        }
+    }
+
+    #[must_use]
+    pub fn has_omissions(&self) -> bool {
+        self.inclusions.iter().any(|i| *i != Inclusion::Full)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::render::Content;
+
+    /// Every file holds its own path as content.
+    struct Paths;
+
+    impl FileSource for Paths {
+        fn open(&self, file_info: &FileInfo) -> io::Result<Box<dyn Content + '_>> {
+            Ok(Box::new(io::Cursor::new(
+                file_info.relative_path.clone().into_bytes(),
+            )))
+        }
+
+        fn modified(&self, _: &FileInfo) -> Option<SystemTime> {
+            None
+        }
+    }
+
+    /// Files whose content is `len` characters, so `len / 4` tokens each
+    /// with [`CharsPerToken`].
+    fn files(lens: &[usize]) -> Vec<FileInfo> {
+        lens.iter()
+            .enumerate()
+            .map(|(i, &len)| {
+                let path = format!("{i}{}", "x".repeat(len - 1));
+                FileInfo {
+                    path: PathBuf::from(&path),
+                    relative_path: path,
+                    extension: String::new(),
+                    diff: None,
+                }
+            })
+            .collect()
+    }
+
+    fn plan(lens: &[usize], max_tokens: usize, policy: BudgetPolicy) -> TokenPlan {
+        let files = files(lens);
+        let options = TokenOptions {
+            tokenizer: Box::new(CharsPerToken),
+            max_tokens: Some(max_tokens),
+            policy,
+            cache: None,
+        };
+        TokenPlan::new(&Paths, &files, &options, 2, &vec![1; files.len()])
+    }
+
+    #[test]
+    fn skip_leaves_out_files_that_do_not_fit_in_path_order() {
+        let plan = plan(&[40, 80, 20], 20, BudgetPolicy::Skip);
+        assert_eq!(plan.file_tokens, [10, 20, 5]);
+        assert_eq!(
+            plan.inclusions,
+            [Inclusion::Full, Inclusion::Omitted, Inclusion::Full]
+        );
+        assert_eq!(plan.total, 2 + 11 + 6);
+    }
+
+    #[test]
+    fn truncate_cuts_the_first_file_that_does_not_fit_and_drops_the_rest() {
+        let plan = plan(&[40, 80, 20], 20, BudgetPolicy::Truncate);
+        assert_eq!(plan.inclusions[0], Inclusion::Full);
+        // 20 - 2 overhead - 11 for the first file - 1 wrapper leaves 6 tokens
+        assert_eq!(
+            plan.inclusions[1],
+            Inclusion::Truncated {
+                tokens: 6,
+                bytes: 24
+            }
+        );
+        assert_eq!(plan.inclusions[2], Inclusion::Omitted);
+        assert_eq!(plan.total, 20);
+    }
+
+    #[test]
+    fn smallest_first_keeps_the_smallest_files_that_fit() {
+        // Costs 11, 21, 6 and 4 against 18 tokens after the overhead
+        let plan = plan(&[40, 80, 20, 12], 20, BudgetPolicy::SmallestFirst);
+        assert_eq!(
+            plan.inclusions,
+            [
+                Inclusion::Omitted,
+                Inclusion::Omitted,
+                Inclusion::Full,
+                Inclusion::Full
+            ]
+        );
+        assert_eq!(plan.total, 2 + 6 + 4);
+    }
+
+    #[test]
+    fn cl100k_prefixes_end_on_character_boundaries() {
+        let tokenizer = Cl100k::new();
+        let text = "日本語のテキスト ".repeat(20);
+        let mut last = 0;
+        for max_tokens in 0..40 {
+            let len = tokenizer.prefix_len(&text, max_tokens);
+            assert!(text.is_char_boundary(len));
+            assert!(len >= last);
+            last = len;
+        }
+        assert_eq!(tokenizer.prefix_len("short", 10), 5);
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::FileInfo;
+
+/// Draw the directory tree of `files` as shown at the top of a report.
+#[must_use]
+pub fn generate_tree_structure(files: &[FileInfo]) -> String {
+    let path_map = build_path_map(files);
+    if files.is_empty()
+        && path_map
+            .get(".")
+            .is_none_or(std::collections::BTreeSet::is_empty)
+    {
+        return ".\n(No files or directories found to list)\n".to_string();
+    }
+
+    let mut output_tree_string = String::new();
+    if path_map.contains_key(".") || !files.is_empty() {
+        output_tree_string.push_str(".\n");
+    }
+    generate_tree_recursive(&path_map, ".", "", &mut output_tree_string, true);
+    output_tree_string
+}
+
+/// Map every directory above the given files, keyed by its path relative to
+/// the root (`.` for the root itself), to the sorted names of its children.
+#[must_use]
+pub fn build_path_map(files: &[FileInfo]) -> HashMap<String, BTreeSet<String>> {
+    let mut path_map: HashMap<String, BTreeSet<String>> = HashMap::new();
+    let mut all_distinct_paths: HashSet<String> = HashSet::new();
+
+    for file_info in files {
+        let path = Path::new(&file_info.relative_path);
+        all_distinct_paths.insert(file_info.relative_path.clone());
+        let mut current_accumulated_path = PathBuf::new();
+        if let Some(parent_dir) = path.parent() {
+            for component in parent_dir.components() {
+                if let Some(comp_str) = component.as_os_str().to_str()
+                    && comp_str != "."
+                    && comp_str != "/"
+                {
+                    current_accumulated_path.push(comp_str);
+                    if !current_accumulated_path.as_os_str().is_empty() {
+                        all_distinct_paths.insert(
+                            current_accumulated_path
+                                .to_string_lossy()
+                                .replace('\\', "/"),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    for path_str in all_distinct_paths {
+        let p = Path::new(&path_str);
+        let file_name_os = p.file_name().unwrap_or(p.as_os_str());
+        let child_name = file_name_os.to_string_lossy().into_owned();
+
+        if let Some(parent_path_os) = p.parent() {
+            let parent_key = if parent_path_os.as_os_str().is_empty() {
+                ".".to_string()
+            } else {
+                parent_path_os.to_string_lossy().replace('\\', "/")
+            };
+            path_map.entry(parent_key).or_default().insert(child_name);
+        } else {
+            path_map
+                .entry(".".to_string())
+                .or_default()
+                .insert(child_name);
+        }
+    }
+    if !path_map.contains_key(".") && !files.is_empty() {
+        for file_info in files {
+            let p = Path::new(&file_info.relative_path);
+            if p.parent().is_none_or(|par| par.as_os_str().is_empty()) {
+                let file_name_os = p.file_name().unwrap_or(p.as_os_str());
+                path_map
+                    .entry(".".to_string())
+                    .or_default()
+                    .insert(file_name_os.to_string_lossy().into_owned());
+            }
+        }
+    }
+    path_map
+}
+
+fn generate_tree_recursive(
+    path_map: &HashMap<String, BTreeSet<String>>,
+    current_path_key: &str,
+    prefix_for_children_lines: &str,
+    output: &mut String,
+    is_current_path_conceptual_root: bool,
+) {
+    if let Some(children_names) = path_map.get(current_path_key) {
+        let num_children = children_names.len();
+        for (i, child_name) in children_names.iter().enumerate() {
+            let is_last = i == num_children - 1;
+
+            output.push_str(prefix_for_children_lines);
+            output.push_str(if is_last { "└── " } else { "├── " });
+            output.push_str(child_name);
+            output.push('\n');
+
+            let child_full_key = if is_current_path_conceptual_root && current_path_key == "." {
+                child_name.clone()
+            } else {
+                format!("{current_path_key}/{child_name}")
+            };
+
+            if path_map.contains_key(&child_full_key) {
+                let mut new_prefix_for_grandchildren = prefix_for_children_lines.to_string();
+                new_prefix_for_grandchildren.push_str(if is_last { "   " } else { "│  " });
+                generate_tree_recursive(
+                    path_map,
+                    &child_full_key,
+                    &new_prefix_for_grandchildren,
+                    output,
+                    false,
+                );
+            }
+        }
+    }
+}
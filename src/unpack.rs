@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use clap::{Args, ValueEnum};
+use rosetree::render::markdown::NO_FINAL_NEWLINE;
+use similar::TextDiff;
+
+/// Recreate the files of a Markdown report in a directory.
+#[derive(Args)]
+pub struct UnpackArgs {
+    /// Report to unpack; pass every part of a split report, in order
+    #[arg(required = true, value_name = "REPORT")]
+    reports: Vec<PathBuf>,
+
+    /// Directory to recreate the files in
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    into: PathBuf,
+
+    /// List what would be written without touching any file
+    #[arg(long)]
+    dry_run: bool,
+
+    /// What to do with existing files whose content differs
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t)]
+    on_conflict: ConflictPolicy,
+
+    /// Print a unified diff against existing files that would change
+    #[arg(long)]
+    diff: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ConflictPolicy {
+    /// Keep the existing file
+    #[default]
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Rename the existing file to `<name>.bak` and write the new one
+    Backup,
+}
+
+/// A file recovered from a report.
+struct ReportFile {
+    relative_path: String,
+    content: String,
+    /// The report only holds the start of the file, cut to a token budget.
+    truncated: bool,
+    /// Blocks seen and expected for a file split across report parts.
+    blocks: Option<(usize, usize)>,
+}
+
+enum Action {
+    Create,
+    Unchanged,
+    Skip,
+    Overwrite,
+    Backup(PathBuf),
+}
+
+impl Action {
+    fn label(&self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Unchanged => "unchanged",
+            Action::Skip => "skip",
+            Action::Overwrite => "overwrite",
+            Action::Backup(_) => "backup",
+        }
+    }
+}
+
+/// Parse the reports and write their files below `args.into`.
+pub fn run(args: &UnpackArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut text = String::new();
+    for report in &args.reports {
+        text.push_str(
+            &fs::read_to_string(report)
+                .map_err(|e| format!("Failed to read {}: {e}", report.display()))?,
+        );
+    }
+    let files = parse_report(&text)?;
+    if files.is_empty() {
+        return Err("No file blocks found in the report".into());
+    }
+
+    // Without an existing target directory nothing can escape it yet
+    let root = if args.into.is_dir() {
+        Some(args.into.canonicalize()?)
+    } else if args.dry_run {
+        None
+    } else {
+        fs::create_dir_all(&args.into)
+            .map_err(|e| format!("Failed to create {}: {e}", args.into.display()))?;
+        Some(args.into.canonicalize()?)
+    };
+
+    eprintln!(
+        "{} {} files into {}:",
+        if args.dry_run {
+            "Would unpack"
+        } else {
+            "Unpacking"
+        },
+        files.len(),
+        args.into.display()
+    );
+    let mut stdout = io::stdout().lock();
+    let mut written = 0;
+    let mut skipped = 0;
+    let mut unchanged = 0;
+
+    for file in &files {
+        if file.truncated {
+            eprintln!(
+                "  skip      {} (truncated in the report)",
+                file.relative_path
+            );
+            skipped += 1;
+            continue;
+        }
+        let Some(relative) = safe_relative_path(&file.relative_path) else {
+            eprintln!("  skip      {} (unsafe path)", file.relative_path);
+            skipped += 1;
+            continue;
+        };
+        let target = args.into.join(&relative);
+        if let Some(root) = &root
+            && escapes_root(root, &target)?
+        {
+            eprintln!(
+                "  skip      {} (outside {})",
+                file.relative_path,
+                args.into.display()
+            );
+            skipped += 1;
+            continue;
+        }
+
+        let existing = match fs::read_to_string(&target) {
+            Ok(existing) => Some(existing),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("Failed to read {}: {e}", target.display()).into()),
+        };
+        let action = match &existing {
+            None => Action::Create,
+            Some(existing) if same_content(existing, &file.content) => Action::Unchanged,
+            Some(_) => match args.on_conflict {
+                ConflictPolicy::Skip => Action::Skip,
+                ConflictPolicy::Overwrite => Action::Overwrite,
+                ConflictPolicy::Backup => Action::Backup(backup_path(&target)),
+            },
+        };
+        eprintln!("  {:<9} {}", action.label(), file.relative_path);
+
+        if args.diff
+            && let Some(existing) = &existing
+            && !matches!(action, Action::Unchanged)
+        {
+            write_diff(&mut stdout, &file.relative_path, existing, &file.content)?;
+        }
+
+        match action {
+            Action::Unchanged => unchanged += 1,
+            Action::Skip => skipped += 1,
+            _ if args.dry_run => written += 1,
+            Action::Create | Action::Overwrite | Action::Backup(_) => {
+                write_file(&target, &action, &file.content)?;
+                written += 1;
+            }
+        }
+    }
+    stdout.flush()?;
+
+    if args.dry_run {
+        eprintln!(
+            "\nDry run: {written} files would be written ({skipped} skipped, {unchanged} unchanged)"
+        );
+    } else {
+        eprintln!("\nWrote {written} files ({skipped} skipped, {unchanged} unchanged)");
+    }
+    Ok(())
+}
+
+fn write_diff(out: &mut dyn Write, path: &str, existing: &str, unpacked: &str) -> io::Result<()> {
+    let diff = TextDiff::from_lines(existing, unpacked);
+    write!(
+        out,
+        "{}",
+        diff.unified_diff()
+            .header(&format!("a/{path}"), &format!("b/{path}"))
+    )
+}
+
+fn write_file(target: &Path, action: &Action, content: &str) -> Result<(), String> {
+    if let Action::Backup(backup) = action {
+        fs::rename(target, backup)
+            .map_err(|e| format!("Failed to back up {}: {e}", target.display()))?;
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+    }
+    fs::write(target, content).map_err(|e| format!("Failed to write {}: {e}", target.display()))
+}
+
+/// Collect the file blocks of a report in order. Blocks labeled `(k of n)`
+/// by a split report are joined back into one file.
+fn parse_report(text: &str) -> Result<Vec<ReportFile>, String> {
+    let mut files: Vec<ReportFile> = Vec::new();
+    let mut index_by_path: HashMap<String, usize> = HashMap::new();
+    let mut lines = text.split_inclusive('\n').peekable();
+
+    while let Some(line) = lines.next() {
+        let Some((path, label)) = parse_heading(line.trim_end()) else {
+            continue;
+        };
+        while lines.peek().is_some_and(|l| l.trim().is_empty()) {
+            lines.next();
+        }
+        let Some(open) = lines.next().and_then(|l| opening_fence_len(l.trim_end())) else {
+            return Err(format!("`{path}` is not followed by a code block"));
+        };
+
+        let mut content = String::new();
+        let mut closed = false;
+        for line in lines.by_ref() {
+            if is_closing_fence(line.trim_end(), open) {
+                closed = true;
+                break;
+            }
+            content.push_str(line);
+        }
+        if !closed {
+            return Err(format!("The code block of `{path}` is not closed"));
+        }
+
+        while lines.peek().is_some_and(|l| l.trim().is_empty()) {
+            lines.next();
+        }
+        // The newline before the closing fence was added by the report
+        if lines
+            .next_if(|l| l.trim_end() == NO_FINAL_NEWLINE)
+            .is_some()
+        {
+            content.pop();
+            while lines.peek().is_some_and(|l| l.trim().is_empty()) {
+                lines.next();
+            }
+        }
+        let truncated = lines
+            .peek()
+            .is_some_and(|l| l.starts_with("*Truncated to "));
+
+        match label {
+            Some((1, _)) | None => {
+                if index_by_path.contains_key(path) {
+                    eprintln!("Warning: `{path}` appears more than once; using the last block");
+                }
+                index_by_path.insert(path.to_string(), files.len());
+                files.push(ReportFile {
+                    relative_path: path.to_string(),
+                    content,
+                    truncated,
+                    blocks: label,
+                });
+            }
+            Some((index, count)) => {
+                let file = index_by_path
+                    .get(path)
+                    .map(|&i| &mut files[i])
+                    .filter(|f| f.blocks == Some((index - 1, count)))
+                    .ok_or_else(|| {
+                        format!(
+                            "Block {index} of {count} of `{path}` does not follow block {}",
+                            index - 1
+                        )
+                    })?;
+                file.content.push_str(&content);
+                file.truncated |= truncated;
+                file.blocks = Some((index, count));
+            }
+        }
+    }
+
+    for file in &files {
+        if let Some((seen, count)) = file.blocks
+            && seen != count
+        {
+            return Err(format!(
+                "`{}` is incomplete ({seen} of {count} blocks); pass every part of the split report",
+                file.relative_path
+            ));
+        }
+    }
+    Ok(files)
+}
+
+/// Parse a file heading as written by `file_heading`: ``### `path` `` with an
+/// optional `(k of n)` continuation label.
+fn parse_heading(line: &str) -> Option<(&str, Option<(usize, usize)>)> {
+    let rest = line.strip_prefix("### `")?;
+    if let Some(path) = rest.strip_suffix('`') {
+        return (!path.is_empty()).then_some((path, None));
+    }
+    let (path, label) = rest.rsplit_once("` (")?;
+    let (index, count) = label.strip_suffix(')')?.split_once(" of ")?;
+    let (index, count) = (index.parse().ok()?, count.parse().ok()?);
+    (!path.is_empty() && index >= 1 && index <= count).then_some((path, Some((index, count))))
+}
+
+/// Length of an opening backtick fence, which may carry a language tag.
+pub fn opening_fence_len(line: &str) -> Option<usize> {
+    let len = line.bytes().take_while(|&b| b == b'`').count();
+    (len >= 3 && !line[len..].contains('`')).then_some(len)
+}
+
+pub fn is_closing_fence(line: &str, open: usize) -> bool {
+    line.len() >= open && line.bytes().all(|b| b == b'`')
+}
+
+/// Accept only plain relative paths, so a report cannot write outside the
+/// target directory through `..`, absolute paths or drive prefixes.
+pub fn safe_relative_path(path: &str) -> Option<PathBuf> {
+    if path.contains('\\') {
+        return None;
+    }
+    let path = Path::new(path);
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        .then(|| path.to_path_buf())
+}
+
+/// Whether writing `target` would leave `root` through a symlink, either the
+/// target itself or one of its existing parent directories.
+pub fn escapes_root(root: &Path, target: &Path) -> io::Result<bool> {
+    if fs::symlink_metadata(target).is_ok_and(|m| m.file_type().is_symlink()) {
+        return Ok(true);
+    }
+    let Some(existing) = target.ancestors().skip(1).find(|a| a.exists()) else {
+        return Ok(true);
+    };
+    Ok(!existing.canonicalize()?.starts_with(root))
+}
+
+/// Reports written before files without a final newline were marked end
+/// every block with one, so such a file is still considered unchanged.
+fn same_content(existing: &str, unpacked: &str) -> bool {
+    existing == unpacked || unpacked.strip_suffix('\n') == Some(existing)
+}
+
+/// `name.bak`, or `name.bak.1`, `name.bak.2`, ... if earlier backups exist.
+fn backup_path(target: &Path) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let mut backup = target.with_file_name(format!("{name}.bak"));
+    let mut n = 1;
+    while backup.exists() {
+        backup = target.with_file_name(format!("{name}.bak.{n}"));
+        n += 1;
+    }
+    backup
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(blocks: &str) -> String {
+        format!("<!-- Generated by rosetree -->\n# Project Analysis Report\n\n{blocks}")
+    }
+
+    #[test]
+    fn blocks_become_files() {
+        let text =
+            report("### `src/a.rs`\n\n```rust\nfn a() {}\n```\n\n### `b.txt`\n\n```\nb\n```\n\n");
+        let files = parse_report(&text).unwrap();
+        let contents: Vec<(&str, &str)> = files
+            .iter()
+            .map(|f| (f.relative_path.as_str(), f.content.as_str()))
+            .collect();
+        assert_eq!(contents, [("src/a.rs", "fn a() {}\n"), ("b.txt", "b\n")]);
+    }
+
+    #[test]
+    fn a_missing_final_newline_is_restored() {
+        let text = report(&format!(
+            "### `a.txt`\n\n```\nno newline\n```\n\n{NO_FINAL_NEWLINE}\n\n### `b.txt`\n\n```\nb\n```\n\n"
+        ));
+        let files = parse_report(&text).unwrap();
+        assert_eq!(files[0].content, "no newline");
+        assert_eq!(files[1].content, "b\n");
+    }
+
+    #[test]
+    fn split_blocks_are_joined_in_order() {
+        let text = report(&format!(
+            "### `a.txt` (1 of 2)\n\n```\none\n```\n\n\
+             ### `a.txt` (2 of 2)\n\n```\ntwo\n```\n\n{NO_FINAL_NEWLINE}\n\n"
+        ));
+        let files = parse_report(&text).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].content, "one\ntwo");
+        assert_eq!(files[0].blocks, Some((2, 2)));
+    }
+
+    #[test]
+    fn split_blocks_out_of_order_or_missing_are_errors() {
+        let out_of_order = report("### `a.txt` (2 of 2)\n\n```\ntwo\n```\n\n");
+        assert_eq!(
+            parse_report(&out_of_order).err().as_deref(),
+            Some("Block 2 of 2 of `a.txt` does not follow block 1")
+        );
+        let missing = report("### `a.txt` (1 of 3)\n\n```\none\n```\n\n");
+        assert!(
+            parse_report(&missing)
+                .err()
+                .is_some_and(|e| e.starts_with("`a.txt` is incomplete (1 of 3 blocks)"))
+        );
+    }
+
+    #[test]
+    fn an_unclosed_block_is_an_error() {
+        let text = report("### `a.txt`\n\n```\ncut off\n");
+        assert_eq!(
+            parse_report(&text).err().as_deref(),
+            Some("The code block of `a.txt` is not closed")
+        );
+    }
+
+    #[test]
+    fn truncated_blocks_are_marked() {
+        let text = report(
+            "### `a.txt`\n\n```\nstart\n```\n\n*Truncated to 10 of 99 estimated tokens.*\n\n",
+        );
+        assert!(parse_report(&text).unwrap()[0].truncated);
+    }
+
+    #[test]
+    fn only_plain_relative_paths_are_safe() {
+        assert_eq!(safe_relative_path("a/b.rs"), Some(PathBuf::from("a/b.rs")));
+        assert!(safe_relative_path("./a/b.rs").is_some());
+        assert!(safe_relative_path("../b.rs").is_none());
+        assert!(safe_relative_path("a/../../b.rs").is_none());
+        assert!(safe_relative_path("/etc/passwd").is_none());
+        assert!(safe_relative_path("a\\b.rs").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_the_root_escape_it() {
+        use std::os::unix::fs::symlink;
+
+        let base = std::env::temp_dir().join(format!("rosetree-unpack-{}", std::process::id()));
+        let root = base.join("root");
+        let outside = base.join("outside");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        symlink(&outside, root.join("link")).unwrap();
+        symlink(outside.join("file"), root.join("file-link")).unwrap();
+        let root = root.canonicalize().unwrap();
+
+        let escapes = |path: &str| escapes_root(&root, &root.join(path)).unwrap();
+        assert!(!escapes("new.txt"));
+        assert!(!escapes("sub/new.txt"));
+        assert!(!escapes("sub/deeper/new.txt"));
+        assert!(escapes("link/new.txt"));
+        assert!(escapes("file-link"));
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}